@@ -9,13 +9,23 @@ struct Cli {
     version: String,
     #[clap(short, long)]
     verbose: bool,
+    /// Maximum amount of documentation to keep in memory, in megabytes
+    #[clap(long)]
+    memory_budget: Option<u64>,
 }
 
 fn main() -> Result<()> {
     let cli = Cli::parse();
     println!("Building Qt {} documentation", cli.version);
-    qt::QtDocumentationGenerator::new(docdelve::container::ContainerEngine::Podman, &cli.version)?
-        .build(default_terminal_progress_event_handler(cli.verbose))?;
+    let mut generator = qt::QtDocumentationGenerator::new(
+        docdelve::container::ContainerEngine::Podman,
+        &cli.version,
+    )?;
+    generator.set_memory_budget(
+        cli.memory_budget
+            .map(|budget| budget.saturating_mul(1024 * 1024)),
+    );
+    generator.build(default_terminal_progress_event_handler(cli.verbose))?;
     println!("\r\x1b[2KBuild completed");
     Ok(())
 }
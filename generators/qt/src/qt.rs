@@ -167,6 +167,12 @@ impl QtDocumentationGenerator {
         })
     }
 
+    /// Sets the maximum amount of documentation contents to keep in memory while building.
+    /// Contents beyond the budget are stored in a temporary file.
+    pub fn set_memory_budget(&mut self, budget: Option<u64>) {
+        self.container.set_memory_budget(budget);
+    }
+
     /// Build the Qt documentation
     pub fn build<F>(&mut self, mut progress: F) -> Result<()>
     where
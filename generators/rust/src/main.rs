@@ -10,6 +10,9 @@ struct Cli {
     command: Command,
     #[clap(short, long)]
     verbose: bool,
+    /// Maximum amount of documentation to keep in memory, in megabytes
+    #[clap(long)]
+    memory_budget: Option<u64>,
 }
 
 #[derive(Subcommand)]
@@ -38,11 +41,15 @@ fn main() -> Result<()> {
                 std.version
             );
 
-            stdlib::StandardLibraryDocumentationGenerator::new(
+            let mut generator = stdlib::StandardLibraryDocumentationGenerator::new(
                 docdelve::container::ContainerEngine::Podman,
                 &std.version,
-            )?
-            .build(default_terminal_progress_event_handler(cli.verbose))?;
+            )?;
+            generator.set_memory_budget(
+                cli.memory_budget
+                    .map(|budget| budget.saturating_mul(1024 * 1024)),
+            );
+            generator.build(default_terminal_progress_event_handler(cli.verbose))?;
             println!("\r\x1b[2KBuild completed");
         }
         Command::Crate(_) => {
@@ -1,5 +1,5 @@
 use anyhow::{anyhow, Error, Result};
-use docdelve::chest::{Chest, ChestListEntry};
+use docdelve::chest::Chest;
 use docdelve::container::{Container, ContainerEngine};
 use docdelve::content::{ChestContents, ChestItem, Page, PageCategory, PageItem, PageLink};
 use docdelve::progress::ProgressEvent;
@@ -57,6 +57,12 @@ impl StandardLibraryDocumentationGenerator {
         })
     }
 
+    /// Sets the maximum amount of documentation contents to keep in memory while building.
+    /// Contents beyond the budget are stored in a temporary file.
+    pub fn set_memory_budget(&mut self, budget: Option<u64>) {
+        self.container.set_memory_budget(budget);
+    }
+
     /// Build the Rust documentation
     pub fn build<F>(&mut self, mut progress: F) -> Result<()>
     where
@@ -118,16 +124,11 @@ impl StandardLibraryDocumentationGenerator {
         Self::add_book(&chest, &mut contents, "unstable-book", "The Unstable Book")?;
 
         // Patch CSS to remove sidebars and search, as these are provided by the app itself.
-        for file in chest.list_dir("static.files")? {
-            if let ChestListEntry::File(file) = file {
-                if file.starts_with("rustdoc-") && file.ends_with(".css") {
-                    let mut css =
-                        String::from_utf8(chest.read(&format!("static.files/{}", file))?)?;
-                    css.push_str("\n.sidebar { display: none; }\n");
-                    css.push_str(".search-form { display: none; }\n");
-                    chest.write(&format!("static.files/{}", file), css.as_bytes())?;
-                }
-            }
+        for path in chest.find_all_glob("static.files/rustdoc-*.css")? {
+            let mut css = String::from_utf8(chest.read(&path)?)?;
+            css.push_str("\n.sidebar { display: none; }\n");
+            css.push_str(".search-form { display: none; }\n");
+            chest.write(&path, css.as_bytes())?;
         }
 
         for path in chest.find_all("chrome.css") {
@@ -0,0 +1,157 @@
+use crate::chest::{Chest, MANIFEST_PATH};
+use crate::content::{ChestInfo, IndexedChestContents, CONTENTS_PATH};
+use anyhow::{anyhow, Result};
+use memmap2::Mmap;
+use std::collections::BTreeSet;
+use std::fs::File;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use tempfile::NamedTempFile;
+
+/// Extension used for cache files in the cache directory
+const CACHE_EXTENSION: &'static str = "bin";
+
+/// Cache of the indexed contents of chests, so that the contents don't need to be indexed
+/// every time a chest is loaded. The contents of each chest are stored in their own file,
+/// named after the chest identifier, using the same binary form as the index stored within
+/// chests. Cached contents are keyed by the hash of the chest's manifest, so that they are
+/// not used once the chest changes.
+pub(crate) struct ContentsCache {
+    path: PathBuf,
+    entries: Vec<CacheEntry>,
+}
+
+/// Cache file that was found when loading the cache.
+struct CacheEntry {
+    path: PathBuf,
+    key: String,
+    info: ChestInfo,
+}
+
+impl ContentsCache {
+    /// Loads the cache from a directory. Only the chest information at the start of each
+    /// cache file is read. Cache files that can't be read are ignored, they are replaced
+    /// when the contents of the chest are cached again.
+    pub fn load(path: &Path) -> Self {
+        let mut entries = Vec::new();
+        if let Ok(dir) = path.read_dir() {
+            for entry in dir.flatten() {
+                let path = entry.path();
+                if path.extension().map(|ext| ext == CACHE_EXTENSION) != Some(true) {
+                    continue;
+                }
+                if let Ok(Some((key, info))) = Self::read_header(&path) {
+                    entries.push(CacheEntry { path, key, info });
+                }
+            }
+        }
+        Self {
+            path: path.to_path_buf(),
+            entries,
+        }
+    }
+
+    /// Gets the key of a chest's contents in the cache. This is the hash of the chest's
+    /// manifest, or the hash of the chest contents if the chest does not have a manifest.
+    pub fn key(chest: &Chest) -> Result<String> {
+        if chest.contains(MANIFEST_PATH) {
+            chest.hash(MANIFEST_PATH)
+        } else {
+            chest.hash(CONTENTS_PATH)
+        }
+    }
+
+    /// Gets the chest information of cached contents that were found when loading the cache.
+    pub fn info(&self, key: &str) -> Option<&ChestInfo> {
+        self.entries
+            .iter()
+            .find(|entry| entry.key == key)
+            .map(|entry| &entry.info)
+    }
+
+    /// Reads the cached contents of a chest. Returns `None` if the contents of the chest are
+    /// not in the cache or were cached for a different version of the chest.
+    pub fn read(&self, identifier: &str, key: &str) -> Result<Option<IndexedChestContents>> {
+        let path = match self.file_path(identifier) {
+            Some(path) if path.is_file() => path,
+            _ => return Ok(None),
+        };
+        let map = Self::map(&path)?;
+        let limit = map.len() as u64;
+        let mut reader = &map[..];
+        match IndexedChestContents::read_index_header(&mut reader, limit)? {
+            Some((cached_key, info)) if cached_key == key => Ok(Some(
+                IndexedChestContents::read_index_items(&mut reader, limit, info)?,
+            )),
+            _ => Ok(None),
+        }
+    }
+
+    /// Writes the contents of a chest to the cache. The cache file is written to a temporary
+    /// file first and then moved into place, so that a cache file is never seen partially
+    /// written and is never modified while it is mapped into memory.
+    pub fn write(&self, key: &str, contents: &IndexedChestContents) -> Result<()> {
+        let path = self.file_path(&contents.info.identifier).ok_or_else(|| {
+            anyhow!(
+                "Chest identifier '{}' can't be used for caching",
+                contents.info.identifier
+            )
+        })?;
+        std::fs::create_dir_all(&self.path)?;
+        let mut file = NamedTempFile::new_in(&self.path)?;
+        file.write_all(&contents.to_index(key)?)?;
+        file.persist(&path)?;
+        Ok(())
+    }
+
+    /// Removes the cached contents of a chest.
+    pub fn remove(&self, identifier: &str) {
+        if let Some(path) = self.file_path(identifier) {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+
+    /// Removes cache files for chests that are no longer in the database, along with cache
+    /// files that are not named after the chest they contain.
+    pub fn remove_unused(&self, identifiers: &BTreeSet<&str>) {
+        for entry in &self.entries {
+            if !identifiers.contains(entry.info.identifier.as_str())
+                || self.file_path(&entry.info.identifier).as_ref() != Some(&entry.path)
+            {
+                let _ = std::fs::remove_file(&entry.path);
+            }
+        }
+    }
+
+    /// Reads the key and chest information from a cache file. Returns `None` if the cache
+    /// file was written for a different chest format.
+    fn read_header(path: &Path) -> Result<Option<(String, ChestInfo)>> {
+        let map = Self::map(path)?;
+        IndexedChestContents::read_index_header(&mut &map[..], map.len() as u64)
+    }
+
+    /// Gets the path of the cache file for a chest identifier. Returns `None` if the
+    /// identifier can't safely be used as a file name.
+    fn file_path(&self, identifier: &str) -> Option<PathBuf> {
+        if identifier.is_empty()
+            || !identifier
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+        {
+            return None;
+        }
+        Some(
+            self.path
+                .join(format!("{}.{}", identifier, CACHE_EXTENSION)),
+        )
+    }
+
+    /// Maps a cache file into memory.
+    fn map(path: &Path) -> Result<Mmap> {
+        let file = File::open(path)?;
+
+        // SAFETY: Cache files are never modified in place. They are only replaced by moving
+        // a new file over them, which leaves the mapped file intact.
+        Ok(unsafe { Mmap::map(&file)? })
+    }
+}
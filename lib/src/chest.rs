@@ -1,30 +1,76 @@
 use crate::progress::ProgressEvent;
+use crate::signature::{ChestSignature, ChestSigningKey, SIGNATURE_PATH};
 use anyhow::{anyhow, Error, Result};
 use diffy::Patch;
-use std::cell::RefCell;
-use std::collections::BTreeMap;
+use rayon::prelude::*;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, BTreeSet};
 use std::fs::File;
-use std::io::{BufReader, BufWriter, Read, Write};
+use std::io::{BufReader, BufWriter, Cursor, Read, Seek, SeekFrom, Write};
 use std::path::{Path, PathBuf};
+use std::sync::{Arc, OnceLock};
+use std::time::SystemTime;
+use zip::read::ZipFile;
 use zip::write::FileOptions;
 use zip::{CompressionMethod, ZipArchive, ZipWriter};
 
 /// Directory listing for a directory within a chest
+#[derive(Clone)]
 struct ChestDirectory {
     contents: BTreeMap<String, ChestDirectoryEntry>,
 }
 
 /// Directory entry for a name in the chest
+#[derive(Clone)]
 enum ChestDirectoryEntry {
     File(ChestFile),
     Directory(Box<ChestDirectory>),
 }
 
-/// Tracks a single file's contents. May either be in memory contents or stored in a zip file
-/// on disk. If stored in a zip file, the path is the same as the path within the chest.
+/// Tracks a single file's contents. May either be in memory contents, stored in a zip file
+/// on disk, stored in a directory on disk, or stored in a temporary file when the chest's
+/// memory budget is exhausted. Files on disk keep a reference to where they are stored, so
+/// they can be moved within the chest or into another chest.
+#[derive(Clone)]
 enum ChestFile {
     InMemoryFile(Vec<u8>),
-    ZipBackedFile,
+    ZipBackedFile(ZipEntry),
+    DirectoryBackedFile(PathBuf),
+    TempFileBackedFile(TempFileEntry),
+}
+
+/// Location of a file's contents within a chest's temporary file
+#[derive(Clone)]
+struct TempFileEntry {
+    file: Arc<File>,
+    offset: u64,
+    size: u64,
+}
+
+/// Location of a file within a zip archive on disk
+#[derive(Clone)]
+struct ZipEntry {
+    archive: Arc<ChestArchive>,
+    index: usize,
+    size: u64,
+}
+
+/// Zip archive on disk that backs files in one or more chests
+struct ChestArchive {
+    zip: ZipArchive<ChestArchiveReader>,
+    path: PathBuf,
+    manifest: OnceLock<ChestManifest>,
+}
+
+/// Entry to be written to the archive when saving a chest
+enum SaveEntry<'a> {
+    Directory(String),
+    InMemoryFile(String, &'a [u8]),
+    ZipBackedFile(String, &'a ZipEntry),
+    DirectoryBackedFile(String, &'a Path),
+    TempFileBackedFile(String, &'a TempFileEntry),
 }
 
 /// Directory listing entry for querying the contents of a chest
@@ -33,12 +79,124 @@ pub enum ChestListEntry {
     Directory(String),
 }
 
+/// Storage that backs a file in a chest
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ChestBacking {
+    InMemory,
+    Zip,
+    Directory,
+    TempFile,
+}
+
+/// Information about a file in a chest, as returned by [Chest::walk]
+#[derive(Clone, Debug)]
+pub struct ChestEntryInfo {
+    /// Full path of the file within the chest
+    pub path: String,
+    /// Uncompressed size of the file
+    pub size: u64,
+    /// Compressed size of the file, if it is stored compressed in a zip file
+    pub compressed_size: Option<u64>,
+    /// Storage that the file is read from
+    pub backing: ChestBacking,
+    /// Last modified time of the file, if it is stored on disk
+    pub modified: Option<SystemTime>,
+}
+
+/// Path of the manifest of file hashes within a chest
+pub const MANIFEST_PATH: &'static str = "_chest_manifest.json";
+
+/// Maximum amount of uncompressed data to compress in parallel before writing it out when
+/// saving a chest
+const SAVE_BATCH_SIZE: u64 = 256 * 1024 * 1024;
+
+/// Size of the read buffer used when reading from a chest archive on disk
+const ARCHIVE_READ_BUFFER_SIZE: usize = 64 * 1024;
+
+/// Maximum number of entries allowed in a chest archive
+const MAX_ARCHIVE_ENTRIES: usize = 1_000_000;
+
+/// Maximum uncompressed size of a single file in a chest archive
+const MAX_ARCHIVE_FILE_SIZE: u64 = 1024 * 1024 * 1024;
+
+/// Maximum uncompressed size of all files in a chest archive
+const MAX_ARCHIVE_TOTAL_SIZE: u64 = 16 * 1024 * 1024 * 1024;
+
+/// Maximum ratio of uncompressed to compressed size for a file in a chest archive. Only files
+/// larger than [MIN_COMPRESSION_RATIO_CHECK_SIZE] are checked, as small files can have high
+/// ratios without being a problem.
+const MAX_COMPRESSION_RATIO: u64 = 1000;
+
+/// Minimum uncompressed size of a file before its compression ratio is checked
+const MIN_COMPRESSION_RATIO_CHECK_SIZE: u64 = 1024 * 1024;
+
+/// File type bits of a Unix file mode
+const UNIX_FILE_TYPE_MASK: u32 = 0o170000;
+
+/// Unix file type for symbolic links
+const UNIX_FILE_TYPE_SYMLINK: u32 = 0o120000;
+
+/// Buffered reader for a chest archive on disk. The file handle is shared between all clones
+/// of the reader, but each clone tracks its own position and buffer. Reads are performed at
+/// explicit offsets, so multiple threads can read from the same archive at the same time
+/// without synchronizing on the file position.
+struct ChestArchiveReader {
+    file: Arc<File>,
+    len: u64,
+    position: u64,
+    buffer: Vec<u8>,
+    buffer_start: u64,
+}
+
+/// Reader for a file within a chest archive that fails if the file's contents do not match
+/// the size declared in the archive. This prevents an archive from expanding to more data
+/// than it claims to contain.
+struct DeclaredSizeReader<R: Read> {
+    inner: R,
+    name: String,
+    remaining: u64,
+}
+
 /// Tracks a bundle of files called a chest. This may either be stored in memory or backed by a
-/// zip file on disk.
+/// zip file or directory on disk. Chests can be read from multiple threads at once.
 pub struct Chest {
     root: ChestDirectory,
-    backing_zip: Option<RefCell<ZipArchive<BufReader<File>>>>,
+    backing_dir: Option<PathBuf>,
     path: Option<PathBuf>,
+    compression_policy: CompressionPolicy,
+    signing_key: Option<ChestSigningKey>,
+    memory_budget: Option<u64>,
+    memory_used: u64,
+    temp_file: Option<Arc<File>>,
+    temp_file_size: u64,
+}
+
+/// Manifest of the SHA-256 hashes of every file in a chest. This is written when saving a
+/// chest and is used to verify that the contents of the chest are intact.
+#[derive(Serialize, Deserialize, Default)]
+pub struct ChestManifest {
+    pub files: BTreeMap<String, String>,
+}
+
+/// Compression to use for a file when saving a chest
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ChestCompression {
+    /// Store the file without any compression
+    Store,
+    /// Compress the file with zstd at the given compression level
+    Zstd(i32),
+}
+
+/// Policy for selecting the compression of each file when saving a chest. A compression set
+/// for the exact path of a file takes priority, followed by the compression for the file's
+/// extension, followed by the compression for the file's size. If none of these apply, the
+/// default compression is used.
+#[derive(Clone)]
+pub struct CompressionPolicy {
+    default: ChestCompression,
+    extensions: BTreeMap<String, ChestCompression>,
+    sizes: BTreeMap<u64, ChestCompression>,
+    paths: BTreeMap<String, ChestCompression>,
 }
 
 impl Chest {
@@ -48,15 +206,40 @@ impl Chest {
             root: ChestDirectory {
                 contents: BTreeMap::new(),
             },
-            backing_zip: None,
+            backing_dir: None,
             path: None,
+            compression_policy: CompressionPolicy::default(),
+            signing_key: None,
+            memory_budget: None,
+            memory_used: 0,
+            temp_file: None,
+            temp_file_size: 0,
         }
     }
 
+    /// Opens a chest on disk. If the path is a directory, such as the output of
+    /// [Chest::extract], files are read from the directory as they are needed. Chest archives
+    /// are rejected if they contain invalid paths or symbolic links, or if they exceed the
+    /// limits on entry count and size.
     pub fn open(path: &Path) -> Result<Self> {
+        if path.is_dir() {
+            return Self::open_dir(path);
+        }
+        Self::open_archive(path, MAX_ARCHIVE_ENTRIES)
+    }
+
+    /// Opens a chest archive on disk, rejecting it if it has more than `max_entries` entries.
+    fn open_archive(path: &Path, max_entries: usize) -> Result<Self> {
         // Open the chest file as a zip archive
-        let chest = BufReader::new(File::open(path)?);
-        let zip = ZipArchive::new(chest)?;
+        let chest = ChestArchiveReader::new(File::open(path)?)?;
+        let mut zip = ZipArchive::new(chest)?;
+        if zip.len() > max_entries {
+            return Err(anyhow!(
+                "Chest has too many entries ({}, the limit is {})",
+                zip.len(),
+                max_entries
+            ));
+        }
 
         // Create the chest structure. Don't place the zip file into the structure yet
         // to avoid needing to borrow it.
@@ -64,27 +247,160 @@ impl Chest {
             root: ChestDirectory {
                 contents: BTreeMap::new(),
             },
-            backing_zip: None,
+            backing_dir: None,
             path: Some(path.to_path_buf()),
+            compression_policy: CompressionPolicy::default(),
+            signing_key: None,
+            memory_budget: None,
+            memory_used: 0,
+            temp_file: None,
+            temp_file_size: 0,
         };
 
-        // Iterate over the entries in the zip archive
-        for name in zip.file_names() {
+        // Iterate over the entries in the zip archive. The central directory is not trusted,
+        // so check each entry against the limits before accepting it.
+        let mut files = Vec::new();
+        let mut total_size: u64 = 0;
+        for index in 0..zip.len() {
+            let file = zip.by_index_raw(index)?;
+            let name = file.name().to_string();
+            if file
+                .unix_mode()
+                .map(|mode| mode & UNIX_FILE_TYPE_MASK == UNIX_FILE_TYPE_SYMLINK)
+                == Some(true)
+            {
+                return Err(anyhow!(
+                    "Entry '{}' is a symbolic link, which is not allowed in chests",
+                    name
+                ));
+            }
+            if name.starts_with('/') {
+                return Err(anyhow!(
+                    "Entry '{}' has an absolute path, which is not allowed in chests",
+                    name
+                ));
+            }
             if name.ends_with("/") {
                 // Skip directories
                 continue;
             }
 
-            // Write an entry for each file to declare that it is found in the zip archive.
+            if file.size() > MAX_ARCHIVE_FILE_SIZE {
+                return Err(anyhow!(
+                    "File '{}' is too large ({} bytes, the limit is {})",
+                    name,
+                    file.size(),
+                    MAX_ARCHIVE_FILE_SIZE
+                ));
+            }
+            if file.size() > MIN_COMPRESSION_RATIO_CHECK_SIZE
+                && file.size() / file.compressed_size().max(1) > MAX_COMPRESSION_RATIO
+            {
+                return Err(anyhow!(
+                    "File '{}' has a suspicious compression ratio ({} bytes compressed to {})",
+                    name,
+                    file.size(),
+                    file.compressed_size()
+                ));
+            }
+            total_size = total_size.saturating_add(file.size());
+            if total_size > MAX_ARCHIVE_TOTAL_SIZE {
+                return Err(anyhow!(
+                    "Chest contents are too large (the limit is {} bytes)",
+                    MAX_ARCHIVE_TOTAL_SIZE
+                ));
+            }
+            files.push((name, index, file.size()));
+        }
+
+        // Write an entry for each file to declare that it is found in the zip archive.
+        // Intermediate directories will be created as needed.
+        let archive = Arc::new(ChestArchive {
+            zip,
+            path: path.to_path_buf(),
+            manifest: OnceLock::new(),
+        });
+        for (name, index, size) in files {
+            let file = ZipEntry {
+                archive: archive.clone(),
+                index,
+                size,
+            };
+            result
+                .write_file_entry(&name, |entry| {
+                    *entry = ChestDirectoryEntry::File(ChestFile::ZipBackedFile(file));
+                    Ok(())
+                })
+                .map_err(|error| anyhow!("Invalid path '{}' in chest: {}", name, error))?;
+        }
+
+        Ok(result)
+    }
+
+    /// Opens a directory on disk as a chest. Files are not read until they are needed.
+    fn open_dir(path: &Path) -> Result<Self> {
+        let mut result = Self::new();
+        for (chest_path, file_path) in Self::dir_files(path)? {
+            // Write an entry for each file to declare that it is found in the directory.
             // Intermediate directories will be created as needed.
-            result.write_file_entry(name, |entry| {
-                *entry = ChestDirectoryEntry::File(ChestFile::ZipBackedFile);
+            result.write_file_entry(&chest_path, |entry| {
+                *entry = ChestDirectoryEntry::File(ChestFile::DirectoryBackedFile(file_path));
                 Ok(())
             })?;
         }
+        result.backing_dir = Some(path.to_path_buf());
+        result.path = Some(path.to_path_buf());
+        Ok(result)
+    }
+
+    /// Creates a new in memory chest with the contents of a directory on disk. Every file name
+    /// in the directory tree must be a valid chest path component. Symbolic links are not
+    /// supported, so that a chest can't include files from outside of the directory.
+    pub fn from_dir(path: &Path) -> Result<Self> {
+        let mut result = Self::new();
+        for (chest_path, file_path) in Self::dir_files(path)? {
+            result.write(&chest_path, &std::fs::read(file_path)?)?;
+        }
+        Ok(result)
+    }
+
+    /// Gets the chest path and on disk path of every file in a directory tree. Every file name
+    /// in the directory tree must be a valid chest path component. Fails if the tree contains
+    /// symbolic links, as they could point outside of the directory.
+    fn dir_files(path: &Path) -> Result<Vec<(String, PathBuf)>> {
+        let mut result = Vec::new();
+
+        // Traverse through the entire directory tree
+        let mut dir_queue: Vec<(Option<String>, PathBuf)> = vec![(None, path.to_path_buf())];
+        while let Some((chest_path, dir_path)) = dir_queue.pop() {
+            for entry in dir_path.read_dir()? {
+                let entry = entry?;
+                let name = entry.file_name().into_string().map_err(|name| {
+                    anyhow!("File name '{}' is not valid UTF-8", name.to_string_lossy())
+                })?;
+                let entry_chest_path = match &chest_path {
+                    Some(chest_path) => format!("{}/{}", chest_path, name),
+                    None => name.clone(),
+                };
+                Self::validate_name(&name).map_err(|error| {
+                    anyhow!("Invalid file name '{}': {}", entry_chest_path, error)
+                })?;
+
+                let file_type = entry.file_type()?;
+                if file_type.is_symlink() {
+                    return Err(anyhow!(
+                        "File '{}' is a symbolic link, which is not allowed in chests",
+                        entry_chest_path
+                    ));
+                } else if file_type.is_dir() {
+                    // Found a subdirectory. Add it to the queue for later.
+                    dir_queue.push((Some(entry_chest_path), entry.path()));
+                } else if file_type.is_file() {
+                    result.push((entry_chest_path, entry.path()));
+                }
+            }
+        }
 
-        // Place the zip file into the structure so that files can be read later
-        result.backing_zip = Some(RefCell::new(zip));
         Ok(result)
     }
 
@@ -93,6 +409,9 @@ impl Chest {
         if name.is_empty() {
             return Err(Error::msg("Path components cannot be empty"));
         }
+        if name == "." || name == ".." {
+            return Err(Error::msg("Path components cannot be '.' or '..'"));
+        }
         for ch in name.chars() {
             // Don't allow any characters that will be invalid in a file name
             // for any major OS, not just the current one.
@@ -252,32 +571,94 @@ impl Chest {
     }
 
     /// Read a file from the chest
-    pub fn read(&self, mut path: &str) -> Result<Vec<u8>> {
+    pub fn read(&self, path: &str) -> Result<Vec<u8>> {
         self.read_file_entry(path, |file| match file {
             ChestFile::InMemoryFile(contents) => Ok(contents.clone()),
-            ChestFile::ZipBackedFile => match &self.backing_zip {
-                Some(zip) => {
-                    // Extract the file from the zip archive
-                    let mut contents = Vec::new();
-                    if path.starts_with("/") {
-                        path = &path[1..];
-                    }
-                    zip.borrow_mut().by_name(path)?.read_to_end(&mut contents)?;
-                    Ok(contents)
-                }
-                None => Err(Error::msg(
-                    "File is backed by a zip file, but no backing zip file is present",
-                )),
-            },
+            ChestFile::ZipBackedFile(file) => file.read(),
+            ChestFile::DirectoryBackedFile(file_path) => Ok(std::fs::read(file_path)?),
+            ChestFile::TempFileBackedFile(file) => file.read(),
+        })
+    }
+
+    /// Read a file from the chest through a reader. The given function will be called with
+    /// a reader for the file's contents, so that only the part of the file that is needed
+    /// has to be read.
+    pub fn read_with<F, T>(&self, path: &str, func: F) -> Result<T>
+    where
+        F: FnOnce(&mut dyn Read) -> Result<T>,
+    {
+        self.read_file_entry(path, |file| match file {
+            ChestFile::InMemoryFile(contents) => func(&mut contents.as_slice()),
+            ChestFile::ZipBackedFile(file) => {
+                let mut existing_zip = file.archive.zip.clone();
+                let mut reader = DeclaredSizeReader::new(existing_zip.by_index(file.index)?);
+                func(&mut reader)
+            }
+            ChestFile::DirectoryBackedFile(file_path) => {
+                func(&mut BufReader::new(File::open(file_path)?))
+            }
+            ChestFile::TempFileBackedFile(file) => func(&mut file.read()?.as_slice()),
+        })
+    }
+
+    /// Gets the size of a file in the chest.
+    pub fn size(&self, path: &str) -> Result<u64> {
+        self.read_file_entry(path, |file| Ok(file.size()))
+    }
+
+    /// Gets the hash of a file's contents, as stored in the manifest. For files backed by a
+    /// chest archive, the hash is taken from the archive's manifest when available, so that
+    /// the file doesn't need to be read.
+    pub fn hash(&self, path: &str) -> Result<String> {
+        self.read_file_entry(path, |file| match file {
+            ChestFile::InMemoryFile(contents) => Ok(ChestManifest::hash(contents)),
+            ChestFile::ZipBackedFile(file) => file.hash(),
+            ChestFile::DirectoryBackedFile(file_path) => {
+                ChestManifest::hash_reader(File::open(file_path)?)
+            }
+            ChestFile::TempFileBackedFile(file) => Ok(ChestManifest::hash(&file.read()?)),
         })
     }
 
     /// Write a file to the chest. If the file already exists, it will be overwritten. If the
-    /// directories that are referenced by the path do not exist, they will be created.
+    /// directories that are referenced by the path do not exist, they will be created. If the
+    /// file would exceed the memory budget, it is stored in a temporary file instead.
     pub fn write(&mut self, path: &str, data: &[u8]) -> Result<()> {
-        self.write_file_entry(path, |entry| {
-            *entry = ChestDirectoryEntry::File(ChestFile::InMemoryFile(data.to_vec()));
-            Ok(())
+        let file = match self.memory_budget {
+            Some(budget) if self.memory_used + data.len() as u64 > budget => {
+                ChestFile::TempFileBackedFile(self.write_temp_file(data)?)
+            }
+            _ => ChestFile::InMemoryFile(data.to_vec()),
+        };
+
+        let added = file.memory_size();
+        let removed = self.write_file_entry(path, |entry| {
+            let removed = entry.memory_size();
+            *entry = ChestDirectoryEntry::File(file);
+            Ok(removed)
+        })?;
+        self.memory_used = self.memory_used + added - removed;
+        Ok(())
+    }
+
+    /// Appends file contents to the temporary file, creating it if needed.
+    fn write_temp_file(&mut self, data: &[u8]) -> Result<TempFileEntry> {
+        let file = match &self.temp_file {
+            Some(file) => file.clone(),
+            None => {
+                let file = Arc::new(tempfile::tempfile()?);
+                self.temp_file = Some(file.clone());
+                file
+            }
+        };
+
+        let offset = self.temp_file_size;
+        TempFileEntry::write_all_at(&file, data, offset)?;
+        self.temp_file_size += data.len() as u64;
+        Ok(TempFileEntry {
+            file,
+            offset,
+            size: data.len() as u64,
         })
     }
 
@@ -321,14 +702,254 @@ impl Chest {
 
         // Remove the directory entry if it exists
         Self::validate_name(filename)?;
-        if current.contents.contains_key(filename) {
-            current.contents.remove(filename);
+        if let Some(entry) = current.contents.remove(filename) {
+            self.memory_used -= entry.memory_size();
             Ok(())
         } else {
             Err(Error::msg("Path not found"))
         }
     }
 
+    /// Removes a directory and all files within it. Fails if the path is not a directory.
+    pub fn remove_dir(&mut self, path: &str) -> Result<()> {
+        let path = path.trim_end_matches('/');
+        self.read_entry(path, |entry| match entry {
+            ChestDirectoryEntry::Directory(_) => Ok(()),
+            ChestDirectoryEntry::File(_) => Err(Error::msg("Path is not a directory")),
+        })?;
+        self.remove(path)
+    }
+
+    /// Renames a file or directory, which may move it into a different directory. If the
+    /// directories that are referenced by the new path do not exist, they will be created.
+    /// Fails if the new path already exists.
+    pub fn rename(&mut self, from: &str, to: &str) -> Result<()> {
+        let from = from.trim_matches('/');
+        let to = to.trim_matches('/');
+        if to.starts_with(&format!("{}/", from)) {
+            return Err(Error::msg("Cannot move a directory into itself"));
+        }
+        if self.read_entry(to, |_| Ok(())).is_ok() {
+            return Err(anyhow!("Path '{}' already exists", to));
+        }
+
+        // Take the entry out of its current location and place it at the new path. If the
+        // new path can't be created, the entry is returned to where it was.
+        let (dir, name) = self.parent_dir_mut(from, false)?;
+        let entry = dir
+            .contents
+            .remove(name)
+            .ok_or_else(|| Error::msg("Path not found"))?;
+        match self.parent_dir_mut(to, true) {
+            Ok((dir, name)) => {
+                dir.contents.insert(name.to_string(), entry);
+                Ok(())
+            }
+            Err(error) => {
+                let (dir, name) = self.parent_dir_mut(from, false)?;
+                dir.contents.insert(name.to_string(), entry);
+                Err(error)
+            }
+        }
+    }
+
+    /// Copies a file or directory to a new path. If the directories that are referenced by the
+    /// new path do not exist, they will be created. Fails if the new path already exists.
+    /// Files on disk are not read, the copy refers to the same backing storage.
+    pub fn copy(&mut self, from: &str, to: &str) -> Result<()> {
+        let from = from.trim_matches('/');
+        let to = to.trim_matches('/');
+        if self.read_entry(to, |_| Ok(())).is_ok() {
+            return Err(anyhow!("Path '{}' already exists", to));
+        }
+
+        let entry = self.read_entry(from, |entry| Ok(entry.clone()))?;
+        let added = entry.memory_size();
+        let (dir, name) = self.parent_dir_mut(to, true)?;
+        dir.contents.insert(name.to_string(), entry);
+        self.memory_used += added;
+        Ok(())
+    }
+
+    /// Merges the contents of another chest into the directory `prefix` within this chest. If
+    /// `prefix` is empty, the contents are merged into the root of this chest. Directories
+    /// that exist in both chests are merged together, but the merge fails if any file in the
+    /// other chest conflicts with an existing path. The manifest and signature of the other
+    /// chest are not merged, as they are regenerated when saving.
+    pub fn merge(&mut self, mut other: Chest, prefix: &str) -> Result<()> {
+        other.root.contents.remove(MANIFEST_PATH);
+        other.root.contents.remove(SIGNATURE_PATH);
+        let added = other.root.memory_size();
+
+        // Find or create the directory to merge into
+        let prefix = prefix.trim_matches('/');
+
+        // Check for conflicts before changing anything, so that a failed merge doesn't
+        // leave a partially merged chest or an empty prefix directory behind
+        let conflict = if prefix.is_empty() {
+            Self::merge_conflict(&self.root, &other.root, prefix)
+        } else {
+            let existing = self.read_entry(prefix, |entry| match entry {
+                ChestDirectoryEntry::Directory(directory) => {
+                    Ok(Some(Self::merge_conflict(directory, &other.root, prefix)))
+                }
+                ChestDirectoryEntry::File(_) => Ok(None),
+            });
+            match existing {
+                Ok(Some(conflict)) => conflict,
+                Ok(None) => return Err(Error::msg("Cannot merge into a path that is a file")),
+                // The prefix directory doesn't exist yet, so nothing in it can conflict
+                Err(_) => None,
+            }
+        };
+        if let Some(conflict) = conflict {
+            return Err(anyhow!(
+                "Cannot merge chests, '{}' exists in both chests",
+                conflict
+            ));
+        }
+
+        let target = if prefix.is_empty() {
+            &mut self.root
+        } else {
+            let (dir, name) = self.parent_dir_mut(prefix, true)?;
+            let entry = dir.contents.entry(name.to_string()).or_insert_with(|| {
+                ChestDirectoryEntry::Directory(Box::new(ChestDirectory {
+                    contents: BTreeMap::new(),
+                }))
+            });
+            match entry {
+                ChestDirectoryEntry::Directory(directory) => directory.as_mut(),
+                ChestDirectoryEntry::File(_) => {
+                    return Err(Error::msg("Cannot merge into a path that is a file"));
+                }
+            }
+        };
+        Self::merge_dir(target, other.root);
+        self.memory_used += added;
+        Ok(())
+    }
+
+    /// Finds the first path in `source` that can't be merged into `target`. Returns `None`
+    /// if the directories can be merged.
+    fn merge_conflict(
+        target: &ChestDirectory,
+        source: &ChestDirectory,
+        path: &str,
+    ) -> Option<String> {
+        for (name, entry) in source.contents.iter() {
+            let entry_path = if path.is_empty() {
+                name.clone()
+            } else {
+                format!("{}/{}", path, name)
+            };
+            match (target.contents.get(name), entry) {
+                (None, _) => (),
+                (
+                    Some(ChestDirectoryEntry::Directory(target_dir)),
+                    ChestDirectoryEntry::Directory(source_dir),
+                ) => {
+                    if let Some(conflict) =
+                        Self::merge_conflict(target_dir, source_dir, &entry_path)
+                    {
+                        return Some(conflict);
+                    }
+                }
+                _ => return Some(entry_path),
+            }
+        }
+        None
+    }
+
+    /// Moves the contents of `source` into `target`, merging directories that exist in both.
+    /// Files in `source` replace files in `target`.
+    fn merge_dir(target: &mut ChestDirectory, source: ChestDirectory) {
+        for (name, entry) in source.contents {
+            match (target.contents.get_mut(&name), entry) {
+                (
+                    Some(ChestDirectoryEntry::Directory(target_dir)),
+                    ChestDirectoryEntry::Directory(source_dir),
+                ) => Self::merge_dir(target_dir, *source_dir),
+                (_, entry) => {
+                    target.contents.insert(name, entry);
+                }
+            }
+        }
+    }
+
+    /// Traverses to the directory that contains the given path, and returns that directory
+    /// along with the final component of the path. If `create` is set, missing directories
+    /// are created, otherwise they cause an error. Directories are only created once the
+    /// entire path has been checked, so a path that can't be created leaves no directories
+    /// behind.
+    fn parent_dir_mut<'a>(
+        &mut self,
+        path: &'a str,
+        create: bool,
+    ) -> Result<(&mut ChestDirectory, &'a str)> {
+        // Split the path into its components
+        let mut parts: Vec<&str> = path.split('/').collect();
+        let filename = match parts.pop() {
+            Some(filename) => filename,
+            None => return Err(Error::msg("Path cannot be empty")),
+        };
+        if !parts.is_empty() && parts[0].is_empty() {
+            // Remove leading slash
+            parts.remove(0);
+        }
+
+        // Check that the missing directories can be created before creating any of them
+        if create {
+            let mut current = Some(&self.root);
+            for part in &parts {
+                Self::validate_name(part)?;
+                current = match current.and_then(|dir| dir.contents.get(*part)) {
+                    Some(ChestDirectoryEntry::Directory(directory)) => Some(directory),
+                    Some(ChestDirectoryEntry::File(_)) => {
+                        return Err(Error::msg(
+                            "Cannot create directory because a file already exists there",
+                        ));
+                    }
+                    None => None,
+                };
+            }
+            Self::validate_name(filename)?;
+        }
+
+        // Traverse into the directory that contains the path
+        let mut current = &mut self.root;
+        for part in parts {
+            // Validate each path component's name
+            Self::validate_name(part)?;
+
+            if create {
+                // Get the directory entry, or create a new directory in its place if it
+                // doesn't exist
+                current.contents.entry(part.to_string()).or_insert_with(|| {
+                    ChestDirectoryEntry::Directory(Box::new(ChestDirectory {
+                        contents: BTreeMap::new(),
+                    }))
+                });
+            }
+
+            match current.contents.get_mut(part) {
+                Some(ChestDirectoryEntry::Directory(directory)) => {
+                    // Follow into directory
+                    current = directory;
+                }
+                Some(ChestDirectoryEntry::File(_)) => {
+                    return Err(Error::msg(
+                        "Cannot create directory because a file already exists there",
+                    ));
+                }
+                None => return Err(Error::msg("Path not found")),
+            }
+        }
+
+        Self::validate_name(filename)?;
+        Ok((current, filename))
+    }
+
     /// Get a directory listing for a directory
     pub fn list_dir(&self, path: &str) -> Result<Vec<ChestListEntry>> {
         // Split the path into its components
@@ -377,134 +998,362 @@ impl Chest {
 
     /// Gets the total size of all files in the chest.
     pub fn total_size(&self) -> u64 {
-        let mut result = 0;
-
-        // Traverse through the entire chest's directory structure
-        let mut dir_queue = vec![(None, &self.root)];
-        while !dir_queue.is_empty() {
-            // Get the next directory to work on
-            let (dir_path, dir) = dir_queue.pop().unwrap();
-
-            let path = if let Some(dir_path) = dir_path {
-                // Non-root directory
-                format!("{}/", dir_path)
-            } else {
-                // Root directory
-                String::new()
-            };
-
-            // Process each entry in the directory
-            for (name, entry) in dir.contents.iter() {
-                match entry {
-                    ChestDirectoryEntry::Directory(subdir) => {
-                        // Found a subdirectory. Add it to the queue for later.
-                        dir_queue.push((Some(format!("{}{}", path, name)), subdir));
-                    }
-                    ChestDirectoryEntry::File(ChestFile::InMemoryFile(contents)) => {
-                        // Found an in memory file. Add it to the total.
-                        result += contents.len() as u64;
-                    }
-                    ChestDirectoryEntry::File(ChestFile::ZipBackedFile) => {
-                        if let Some(existing_zip) = &self.backing_zip {
-                            let file_path = format!("{}{}", path, name);
-                            result += existing_zip
-                                .borrow_mut()
-                                .by_name(&file_path)
-                                .map(|file| file.size())
-                                .unwrap_or(0);
-                        }
-                    }
-                }
-            }
-        }
-
-        result
+        self.files().map(|(_, file)| file.size()).sum()
     }
 
-    /// Saves the chest contents to a new zip archive
+    /// Saves the chest contents to a new zip archive. Files that are unchanged from the
+    /// backing zip archive are copied as is, without decompressing and recompressing them.
+    /// A new manifest is always written. The manifest is signed if a signing key has been
+    /// set. Otherwise, an existing signature is kept only if it is valid for the new manifest.
     pub fn save<F>(&mut self, path: &Path, mut progress: F) -> Result<()>
     where
         F: FnMut(ProgressEvent),
     {
-        // Create the zip archive. Use zstd compression as it is much faster than deflate.
+        // Gather the list of entries to write to the archive
+        let entries = self.save_entries();
+
+        // Creating the new archive would destroy a backing zip archive if it is the same file
+        if path.exists() {
+            let target_path = std::fs::canonicalize(path)?;
+            let mut archive_paths = BTreeSet::new();
+            for entry in &entries {
+                if let SaveEntry::ZipBackedFile(_, file) = entry {
+                    archive_paths.insert(file.archive.path.as_path());
+                }
+            }
+            for archive_path in archive_paths {
+                if std::fs::canonicalize(archive_path)? == target_path {
+                    return Err(Error::msg(
+                        "Cannot save a chest over a zip file that is backing it",
+                    ));
+                }
+            }
+        }
+
+        // Create the zip archive
         let chest = BufWriter::new(File::create(path)?);
         let mut zip = ZipWriter::new(chest);
-        let options = FileOptions::default()
-            .compression_method(CompressionMethod::Zstd)
-            .compression_level(Some(7));
+        let directory_options = FileOptions::default();
+        let mut manifest = ChestManifest::default();
 
-        // Traverse through the entire chest's directory structure
-        let mut dir_queue: Vec<(Option<String>, &ChestDirectory)> = vec![(None, &self.root)];
+        // Process the entries in batches. The in memory files within each batch are compressed
+        // in parallel, and then the batch is written to the archive in order. Batches are
+        // limited in size to keep the amount of compressed data held in memory bounded.
         let mut done = 0;
         let total = self.total_size();
-        while !dir_queue.is_empty() {
-            // Get the next directory to work on
-            let (dir_path, dir) = dir_queue.pop().unwrap();
-
-            let path = if let Some(dir_path) = dir_path {
-                // Non-root directory. Add the directory path to the zip archive
-                zip.add_directory(dir_path.to_string(), options)?;
-                format!("{}/", dir_path)
-            } else {
-                // Root directory
-                String::new()
-            };
+        let mut batch_start = 0;
+        while batch_start < entries.len() {
+            // Collect entries into the batch until the size limit is reached
+            let mut batch_end = batch_start;
+            let mut batch_size = 0;
+            while batch_end < entries.len() && batch_size < SAVE_BATCH_SIZE {
+                match &entries[batch_end] {
+                    SaveEntry::InMemoryFile(_, contents) => batch_size += contents.len() as u64,
+                    SaveEntry::DirectoryBackedFile(_, file_path) => {
+                        batch_size += std::fs::metadata(file_path)?.len()
+                    }
+                    SaveEntry::TempFileBackedFile(_, file) => batch_size += file.size,
+                    _ => (),
+                }
+                batch_end += 1;
+            }
+            let batch = &entries[batch_start..batch_end];
+
+            // Compress and hash the files in the batch that are not already compressed using all
+            // available threads. Zip backed files only need to be hashed if they weren't in the
+            // manifest of their zip archive, unless the manifest is being signed. A signature
+            // must only cover hashes of the actual contents.
+            let prepared = batch
+                .par_iter()
+                .map(|entry| match entry {
+                    SaveEntry::Directory(_) => Ok((None, None)),
+                    SaveEntry::InMemoryFile(path, contents) => self.prepare_file(path, contents),
+                    SaveEntry::DirectoryBackedFile(path, file_path) => {
+                        self.prepare_file(path, &std::fs::read(file_path)?)
+                    }
+                    SaveEntry::TempFileBackedFile(path, file) => {
+                        self.prepare_file(path, &file.read()?)
+                    }
+                    SaveEntry::ZipBackedFile(_, file) if self.signing_key.is_some() => {
+                        Ok((None, Some(file.compute_hash()?)))
+                    }
+                    SaveEntry::ZipBackedFile(_, file) => Ok((None, Some(file.hash()?))),
+                })
+                .collect::<Result<Vec<_>>>()?;
+
+            // Write the batch to the archive in order
+            for (entry, (compressed, hash)) in batch.iter().zip(prepared) {
+                if let (
+                    SaveEntry::InMemoryFile(path, _)
+                    | SaveEntry::ZipBackedFile(path, _)
+                    | SaveEntry::DirectoryBackedFile(path, _)
+                    | SaveEntry::TempFileBackedFile(path, _),
+                    Some(hash),
+                ) = (entry, hash)
+                {
+                    manifest.files.insert(path.clone(), hash);
+                }
 
-            // Process each entry in the directory
-            for (name, entry) in dir.contents.iter() {
                 match entry {
-                    ChestDirectoryEntry::Directory(subdir) => {
-                        // Found a subdirectory. Add it to the queue for later.
-                        dir_queue.push((Some(format!("{}{}", path, name)), subdir));
+                    SaveEntry::Directory(path) => {
+                        zip.add_directory(path.to_string(), directory_options)?;
                     }
-                    ChestDirectoryEntry::File(ChestFile::InMemoryFile(contents)) => {
-                        // Found an in memory file. Add it to the zip archive.
-                        zip.start_file(format!("{}{}", path, name), options)?;
-                        zip.write_all(contents)?;
+                    SaveEntry::InMemoryFile(_, contents) => {
+                        // Copy the already compressed file into the archive
+                        let compressed =
+                            compressed.ok_or_else(|| Error::msg("File was not compressed"))?;
+                        let mut compressed_zip = ZipArchive::new(Cursor::new(compressed))?;
+                        zip.raw_copy_file(compressed_zip.by_index_raw(0)?)?;
 
                         done += contents.len() as u64;
                         progress(ProgressEvent::CompressChest(done, total));
                     }
-                    ChestDirectoryEntry::File(ChestFile::ZipBackedFile) => {
-                        // Found a zip backed file. First read the file contents from the existing
-                        // zip archive.
-                        let mut contents = Vec::new();
-                        let file_path = format!("{}{}", path, name);
-                        let size = if let Some(existing_zip) = &self.backing_zip {
-                            let mut zip = existing_zip.borrow_mut();
-                            let mut file = zip.by_name(&file_path)?;
-                            let size = file.size();
-                            file.read_to_end(&mut contents)?;
-                            size
-                        } else {
-                            return Err(Error::msg(
-                                "File is backed by a zip file, but no backing zip file is present",
-                            ));
-                        };
-
-                        // Write the contents to the new zip archive
-                        zip.start_file(file_path, options)?;
-                        zip.write_all(&contents)?;
+                    SaveEntry::DirectoryBackedFile(..) | SaveEntry::TempFileBackedFile(..) => {
+                        // Copy the already compressed file into the archive
+                        let compressed =
+                            compressed.ok_or_else(|| Error::msg("File was not compressed"))?;
+                        let mut compressed_zip = ZipArchive::new(Cursor::new(compressed))?;
+                        let file = compressed_zip.by_index_raw(0)?;
+                        let size = file.size();
+                        zip.raw_copy_file(file)?;
 
                         done += size;
                         progress(ProgressEvent::CompressChest(done, total));
                     }
+                    SaveEntry::ZipBackedFile(path, file) => {
+                        // The file is unchanged, so copy the compressed contents directly
+                        // from the existing zip archive. The file may have been moved within
+                        // the chest, so it is written with its current path.
+                        let mut existing_zip = file.archive.zip.clone();
+                        zip.raw_copy_file_rename(existing_zip.by_index_raw(file.index)?, path)?;
+
+                        done += file.size;
+                        progress(ProgressEvent::CompressChest(done, total));
+                    }
                 }
             }
+
+            batch_start = batch_end;
+        }
+
+        // Write the manifest of file hashes so that the archive can be verified later
+        drop(entries);
+        let manifest = serde_json::to_string(&manifest)?;
+        zip.start_file(
+            MANIFEST_PATH,
+            self.compression_policy
+                .compression_for(MANIFEST_PATH, manifest.len() as u64)
+                .file_options(),
+        )?;
+        zip.write_all(manifest.as_bytes())?;
+
+        // Sign the manifest if requested
+        let signature = match &self.signing_key {
+            Some(key) => Some(serde_json::to_string(&key.sign(manifest.as_bytes()))?),
+            None => match ChestSignature::read_from_chest(self) {
+                Ok(Some(existing)) if existing.verify(manifest.as_bytes()).is_ok() => {
+                    Some(serde_json::to_string(&existing)?)
+                }
+                _ => None,
+            },
+        };
+        if let Some(signature) = &signature {
+            zip.start_file(
+                SIGNATURE_PATH,
+                self.compression_policy
+                    .compression_for(SIGNATURE_PATH, signature.len() as u64)
+                    .file_options(),
+            )?;
+            zip.write_all(signature.as_bytes())?;
         }
 
         // Finalize the zip archive
         zip.finish()?;
 
+        self.write(MANIFEST_PATH, manifest.as_bytes())?;
+        match &signature {
+            Some(signature) => self.write(SIGNATURE_PATH, signature.as_bytes())?,
+            None => {
+                if self.contains(SIGNATURE_PATH) {
+                    self.remove(SIGNATURE_PATH)?;
+                }
+            }
+        }
         self.path = Some(path.to_path_buf());
         Ok(())
     }
 
+    /// Verifies that the contents of the chest match the hashes in its manifest. Fails if
+    /// the chest has no manifest, if any file does not match, or if any file is missing or
+    /// is not listed in the manifest.
+    pub fn verify<F>(&self, mut progress: F) -> Result<()>
+    where
+        F: FnMut(ProgressEvent),
+    {
+        let manifest = ChestManifest::read_from_chest(self)?;
+
+        // Check that every file in the chest is listed in the manifest
+        for path in self.file_paths() {
+            if !manifest.files.contains_key(&path) {
+                return Err(anyhow!(
+                    "File '{}' is not listed in the chest manifest",
+                    path
+                ));
+            }
+        }
+
+        // Check the hash of every file in the manifest
+        let mut done = 0;
+        let total = self.total_size();
+        for (path, expected_hash) in &manifest.files {
+            let (hash, size) = self
+                .read_file_entry(path, |file| match file {
+                    ChestFile::InMemoryFile(contents) => {
+                        Ok((ChestManifest::hash(contents), contents.len() as u64))
+                    }
+                    ChestFile::ZipBackedFile(file) => {
+                        let mut existing_zip = file.archive.zip.clone();
+                        let reader = DeclaredSizeReader::new(existing_zip.by_index(file.index)?);
+                        Ok((ChestManifest::hash_reader(reader)?, file.size))
+                    }
+                    ChestFile::DirectoryBackedFile(file_path) => {
+                        let contents = std::fs::read(file_path)?;
+                        Ok((ChestManifest::hash(&contents), contents.len() as u64))
+                    }
+                    ChestFile::TempFileBackedFile(file) => {
+                        Ok((ChestManifest::hash(&file.read()?), file.size))
+                    }
+                })
+                .map_err(|error| anyhow!("File '{}' could not be verified: {}", path, error))?;
+
+            if &hash != expected_hash {
+                return Err(anyhow!("File '{}' does not match the chest manifest", path));
+            }
+
+            done += size;
+            progress(ProgressEvent::VerifyChest(done, total));
+        }
+
+        Ok(())
+    }
+
+    /// Gets the full path of every file in the chest, except for the manifest and signature.
+    fn file_paths(&self) -> Vec<String> {
+        self.save_entries()
+            .into_iter()
+            .filter_map(|entry| match entry {
+                SaveEntry::Directory(_) => None,
+                SaveEntry::InMemoryFile(path, _)
+                | SaveEntry::ZipBackedFile(path, _)
+                | SaveEntry::DirectoryBackedFile(path, _)
+                | SaveEntry::TempFileBackedFile(path, _) => Some(path),
+            })
+            .collect()
+    }
+
+    /// Gets the list of entries to write when saving the chest, in the order that they will
+    /// appear in the archive.
+    fn save_entries(&self) -> Vec<SaveEntry<'_>> {
+        // Traverse through the entire chest's directory structure
+        let mut dir_queue: Vec<(Option<String>, &ChestDirectory)> = vec![(None, &self.root)];
+        let mut result = Vec::new();
+        while !dir_queue.is_empty() {
+            // Get the next directory to work on
+            let (dir_path, dir) = dir_queue.pop().unwrap();
+
+            let path = if let Some(dir_path) = dir_path {
+                // Non-root directory. Add the directory path to the zip archive
+                let path = format!("{}/", dir_path);
+                result.push(SaveEntry::Directory(dir_path));
+                path
+            } else {
+                // Root directory
+                String::new()
+            };
+
+            // Process each entry in the directory
+            for (name, entry) in dir.contents.iter() {
+                match entry {
+                    ChestDirectoryEntry::Directory(subdir) => {
+                        // Found a subdirectory. Add it to the queue for later.
+                        dir_queue.push((Some(format!("{}{}", path, name)), subdir));
+                    }
+                    ChestDirectoryEntry::File(_)
+                        if path.is_empty() && (name == MANIFEST_PATH || name == SIGNATURE_PATH) =>
+                    {
+                        // The manifest and signature are generated when saving, the existing
+                        // ones are not kept
+                    }
+                    ChestDirectoryEntry::File(ChestFile::InMemoryFile(contents)) => {
+                        result.push(SaveEntry::InMemoryFile(
+                            format!("{}{}", path, name),
+                            contents,
+                        ));
+                    }
+                    ChestDirectoryEntry::File(ChestFile::ZipBackedFile(file)) => {
+                        result.push(SaveEntry::ZipBackedFile(format!("{}{}", path, name), file));
+                    }
+                    ChestDirectoryEntry::File(ChestFile::DirectoryBackedFile(file_path)) => {
+                        result.push(SaveEntry::DirectoryBackedFile(
+                            format!("{}{}", path, name),
+                            file_path,
+                        ));
+                    }
+                    ChestDirectoryEntry::File(ChestFile::TempFileBackedFile(file)) => {
+                        result.push(SaveEntry::TempFileBackedFile(
+                            format!("{}{}", path, name),
+                            file,
+                        ));
+                    }
+                }
+            }
+        }
+        result
+    }
+
+    /// Compresses and hashes a file's contents for saving. Returns the compressed file and
+    /// the hash for the manifest.
+    fn prepare_file(
+        &self,
+        path: &str,
+        contents: &[u8],
+    ) -> Result<(Option<Vec<u8>>, Option<String>)> {
+        let compression = self
+            .compression_policy
+            .compression_for(path, contents.len() as u64);
+        let compressed = Self::compress_file(path, contents, compression.file_options())?;
+        Ok((Some(compressed), Some(ChestManifest::hash(contents))))
+    }
+
+    /// Compresses a single file into a standalone zip archive. This allows files to be
+    /// compressed independently of each other, with the compressed data copied into the
+    /// final archive afterwards.
+    fn compress_file(path: &str, contents: &[u8], options: FileOptions) -> Result<Vec<u8>> {
+        let mut zip = ZipWriter::new(Cursor::new(Vec::new()));
+        zip.start_file(path, options)?;
+        zip.write_all(contents)?;
+        Ok(zip.finish()?.into_inner())
+    }
+
     /// Extracts the chest contents to a directory
     pub fn extract<F>(&self, path: &Path, mut progress: F) -> Result<()>
     where
         F: FnMut(ProgressEvent),
     {
+        // Extracting into the backing directory would truncate the files being copied
+        if let Some(backing_dir) = &self.backing_dir {
+            if path.exists() && std::fs::canonicalize(backing_dir)? == std::fs::canonicalize(path)?
+            {
+                return Err(Error::msg(
+                    "Cannot extract a chest into the directory that is backing it",
+                ));
+            }
+        }
+
+        // Resolve the target directory, so that extracted paths can be checked against it
+        std::fs::create_dir_all(path)?;
+        let root = std::fs::canonicalize(path)?;
+
         // Traverse through the entire chest's directory structure
         let mut dir_queue: Vec<(Option<PathBuf>, Option<String>, &ChestDirectory)> =
             vec![(None, None, &self.root)];
@@ -536,6 +1385,7 @@ impl Chest {
                         // Found a subdirectory. Add it to the queue for later.
                         let mut target_path = target_path.clone();
                         target_path.push(name);
+                        Self::check_extract_target(&root, &target_path)?;
                         dir_queue.push((
                             Some(target_path),
                             Some(format!("{}{}", src_path, name)),
@@ -546,34 +1396,42 @@ impl Chest {
                         // Found an in memory file. Write it to the directory.
                         let mut target_path = target_path.clone();
                         target_path.push(name);
+                        Self::check_extract_target(&root, &target_path)?;
                         std::fs::write(&target_path, contents)?;
 
                         done += contents.len() as u64;
                         progress(ProgressEvent::ExtractChest(done, total));
                     }
-                    ChestDirectoryEntry::File(ChestFile::ZipBackedFile) => {
+                    ChestDirectoryEntry::File(ChestFile::ZipBackedFile(file)) => {
                         // Found a zip backed file. First read the file contents from the existing
                         // zip archive.
-                        let mut contents = Vec::new();
                         let mut target_path = target_path.clone();
                         target_path.push(name);
-                        let src_path = format!("{}{}", src_path, name);
-                        let size = if let Some(existing_zip) = &self.backing_zip {
-                            let mut zip = existing_zip.borrow_mut();
-                            let mut file = zip.by_name(&src_path)?;
-                            let size = file.size();
-                            file.read_to_end(&mut contents)?;
-                            size
-                        } else {
-                            return Err(Error::msg(
-                                "File is backed by a zip file, but no backing zip file is present",
-                            ));
-                        };
+                        Self::check_extract_target(&root, &target_path)?;
+                        let contents = file.read()?;
 
                         // Write the contents to the directory
                         std::fs::write(&target_path, &contents)?;
 
-                        done += size;
+                        done += file.size;
+                        progress(ProgressEvent::ExtractChest(done, total));
+                    }
+                    ChestDirectoryEntry::File(ChestFile::DirectoryBackedFile(file_path)) => {
+                        // Found a directory backed file. Copy it to the directory.
+                        let mut target_path = target_path.clone();
+                        target_path.push(name);
+                        Self::check_extract_target(&root, &target_path)?;
+                        done += std::fs::copy(file_path, &target_path)?;
+                        progress(ProgressEvent::ExtractChest(done, total));
+                    }
+                    ChestDirectoryEntry::File(ChestFile::TempFileBackedFile(file)) => {
+                        // Found a file in the temporary file. Write it to the directory.
+                        let mut target_path = target_path.clone();
+                        target_path.push(name);
+                        Self::check_extract_target(&root, &target_path)?;
+                        std::fs::write(&target_path, file.read()?)?;
+
+                        done += file.size;
                         progress(ProgressEvent::ExtractChest(done, total));
                     }
                 }
@@ -583,16 +1441,89 @@ impl Chest {
         Ok(())
     }
 
+    /// Checks that a path being written during extraction stays within the target directory.
+    /// Existing symbolic links are rejected, as writing through them could escape the target.
+    /// The parent directory of the path must already exist, it is resolved and compared
+    /// against the resolved target directory in `root`.
+    fn check_extract_target(root: &Path, target_path: &Path) -> Result<()> {
+        if let Ok(metadata) = std::fs::symlink_metadata(target_path) {
+            if metadata.file_type().is_symlink() {
+                return Err(anyhow!(
+                    "Path '{}' is a symbolic link, refusing to extract through it",
+                    target_path.display()
+                ));
+            }
+        }
+        let parent = target_path
+            .parent()
+            .ok_or_else(|| anyhow!("Path '{}' has no parent", target_path.display()))?;
+        if !std::fs::canonicalize(parent)?.starts_with(root) {
+            return Err(anyhow!(
+                "Path '{}' is outside of the extraction directory",
+                target_path.display()
+            ));
+        }
+        Ok(())
+    }
+
+    /// Gets the policy used to select the compression of each file when saving the chest.
+    pub fn compression_policy(&self) -> &CompressionPolicy {
+        &self.compression_policy
+    }
+
+    /// Gets the policy used to select the compression of each file when saving the chest,
+    /// so that it can be adjusted.
+    pub fn compression_policy_mut(&mut self) -> &mut CompressionPolicy {
+        &mut self.compression_policy
+    }
+
+    /// Sets the policy used to select the compression of each file when saving the chest.
+    /// Files that are unchanged from the backing zip archive keep their existing compression.
+    pub fn set_compression_policy(&mut self, policy: CompressionPolicy) {
+        self.compression_policy = policy;
+    }
+
+    /// Sets the maximum amount of file contents to keep in memory. Files that are written
+    /// once the budget is exhausted are stored in a temporary file on disk instead, which is
+    /// deleted when the chest is dropped. Files already in the chest are not moved. If
+    /// `None`, all written files are kept in memory.
+    pub fn set_memory_budget(&mut self, budget: Option<u64>) {
+        self.memory_budget = budget;
+    }
+
+    /// Gets the amount of memory used by the contents of files in the chest. Files that
+    /// are backed by storage on disk are not included.
+    pub fn memory_usage(&self) -> u64 {
+        self.memory_used
+    }
+
+    /// Sets the key used to sign the chest manifest when saving the chest. If `None`, the
+    /// chest is saved without a signature.
+    pub fn set_signing_key(&mut self, key: Option<ChestSigningKey>) {
+        self.signing_key = key;
+    }
+
     /// Gets the on disk path of the chest, if it is on disk.
     pub fn path(&self) -> Option<&Path> {
         self.path.as_ref().map(|path| path.as_path())
     }
 
-    /// Closes the chest and deletes it from disk.
+    /// Returns true if the chest is backed by a directory on disk instead of an archive.
+    pub fn is_dir(&self) -> bool {
+        self.backing_dir.is_some()
+    }
+
+    /// Closes the chest and deletes it from disk. Chests backed by a directory are not
+    /// deleted, as the directory may contain files that are not part of the chest.
     pub fn delete(mut self) -> Result<()> {
+        if self.is_dir() {
+            return Err(anyhow!(
+                "Chest is backed by a directory and can't be deleted"
+            ));
+        }
         if let Some(path) = self.path {
             // Need to close the file first or the deletion will fail on Windows.
-            self.backing_zip.take();
+            self.root.contents.clear();
 
             Ok(std::fs::remove_file(path)?)
         } else {
@@ -603,34 +1534,121 @@ impl Chest {
     /// Finds all occurrences of a filename in the chest and returns a list of paths to
     /// those files.
     pub fn find_all(&self, filename: &str) -> Vec<String> {
-        // Traverse through the entire chest's directory structure
+        self.files()
+            .filter(|(path, _)| path.rsplit('/').next() == Some(filename))
+            .map(|(path, _)| path)
+            .collect()
+    }
+
+    /// Finds all files in the chest with a full path that matches a glob pattern and returns
+    /// a list of paths to those files. Wildcards do not match across directories, use `**`
+    /// to match any number of directories.
+    pub fn find_all_glob(&self, pattern: &str) -> Result<Vec<String>> {
+        let pattern = glob::Pattern::new(pattern)?;
+        let options = glob::MatchOptions {
+            case_sensitive: true,
+            require_literal_separator: true,
+            require_literal_leading_dot: false,
+        };
+        Ok(self
+            .files()
+            .filter(|(path, _)| pattern.matches_with(path, options))
+            .map(|(path, _)| path)
+            .collect())
+    }
+
+    /// Finds all files in the chest with a full path that matches a regular expression and
+    /// returns a list of paths to those files.
+    pub fn find_all_regex(&self, regex: &Regex) -> Vec<String> {
+        self.files()
+            .filter(|(path, _)| regex.is_match(path))
+            .map(|(path, _)| path)
+            .collect()
+    }
+
+    /// Iterates over every file in the chest, along with information about each file.
+    /// Information that can't be read from the backing storage is left empty.
+    pub fn walk(&self) -> impl Iterator<Item = ChestEntryInfo> + '_ {
+        self.files().map(|(path, file)| match file {
+            ChestFile::InMemoryFile(contents) => ChestEntryInfo {
+                path,
+                size: contents.len() as u64,
+                compressed_size: None,
+                backing: ChestBacking::InMemory,
+                modified: None,
+            },
+            ChestFile::ZipBackedFile(file) => {
+                let mut existing_zip = file.archive.zip.clone();
+                let zip_file = existing_zip.by_index_raw(file.index).ok();
+                ChestEntryInfo {
+                    size: file.size,
+                    compressed_size: zip_file.as_ref().map(|zip_file| zip_file.compressed_size()),
+                    backing: ChestBacking::Zip,
+                    modified: zip_file.and_then(|zip_file| {
+                        zip_file
+                            .last_modified()
+                            .to_time()
+                            .ok()
+                            .map(|time| time.into())
+                    }),
+                    path,
+                }
+            }
+            ChestFile::DirectoryBackedFile(file_path) => {
+                let metadata = std::fs::metadata(file_path).ok();
+                ChestEntryInfo {
+                    size: metadata
+                        .as_ref()
+                        .map(|metadata| metadata.len())
+                        .unwrap_or(0),
+                    compressed_size: None,
+                    backing: ChestBacking::Directory,
+                    modified: metadata.and_then(|metadata| metadata.modified().ok()),
+                    path,
+                }
+            }
+            ChestFile::TempFileBackedFile(file) => ChestEntryInfo {
+                path,
+                size: file.size,
+                compressed_size: None,
+                backing: ChestBacking::TempFile,
+                modified: None,
+            },
+        })
+    }
+
+    /// Iterates over the full path of every file in the chest, along with the file entry.
+    /// Directories are traversed as the iterator advances.
+    fn files(&self) -> impl Iterator<Item = (String, &ChestFile)> {
         let mut dir_queue: Vec<(Option<String>, &ChestDirectory)> = vec![(None, &self.root)];
-        let mut result = Vec::new();
-        while !dir_queue.is_empty() {
+        let mut pending_files = Vec::new();
+        std::iter::from_fn(move || loop {
+            if let Some(file) = pending_files.pop() {
+                return Some(file);
+            }
+
             // Get the next directory to work on
-            let (src_path, dir) = dir_queue.pop().unwrap();
+            let (src_path, dir) = dir_queue.pop()?;
             let src_path = if let Some(src_path) = src_path {
                 format!("{}/", src_path)
             } else {
                 String::new()
             };
 
-            // Process each entry in the directory
-            for (name, entry) in dir.contents.iter() {
+            // Process each entry in the directory. Files are queued in reverse so that they
+            // are returned in order.
+            for (name, entry) in dir.contents.iter().rev() {
                 match entry {
                     ChestDirectoryEntry::Directory(subdir) => {
                         // Found a subdirectory. Add it to the queue for later.
                         dir_queue.push((Some(format!("{}{}", src_path, name)), subdir));
                     }
-                    ChestDirectoryEntry::File(_) => {
-                        if name == filename {
-                            result.push(format!("{}{}", src_path, name));
-                        }
+                    ChestDirectoryEntry::File(file) => {
+                        pending_files.push((format!("{}{}", src_path, name), file));
                     }
                 }
             }
-        }
-        result
+        })
     }
 
     /// Applies a patch to a file within the chest.
@@ -665,3 +1683,589 @@ impl Chest {
         None
     }
 }
+
+impl ChestManifest {
+    /// Read the manifest of a chest.
+    pub fn read_from_chest(chest: &Chest) -> Result<Self> {
+        let contents = chest
+            .read(MANIFEST_PATH)
+            .map_err(|_| Error::msg("Chest does not have an integrity manifest"))?;
+        Ok(serde_json::from_slice(&contents)?)
+    }
+
+    /// Read the manifest directly from a chest's zip archive.
+    fn read_from_zip(zip: &mut ZipArchive<ChestArchiveReader>) -> Result<Self> {
+        let mut contents = Vec::new();
+        DeclaredSizeReader::new(zip.by_name(MANIFEST_PATH)?).read_to_end(&mut contents)?;
+        Ok(serde_json::from_slice(&contents)?)
+    }
+
+    /// Computes the manifest for the current contents of a chest. This reads every file in the
+    /// chest, so prefer [ChestManifest::read_from_chest] when the chest has a manifest.
+    pub fn compute(chest: &Chest) -> Result<Self> {
+        let mut result = Self::default();
+        for path in chest.file_paths() {
+            result
+                .files
+                .insert(path.clone(), Self::hash(&chest.read(&path)?));
+        }
+        Ok(result)
+    }
+
+    /// Computes the hash of a file's contents, as stored in the manifest.
+    pub fn hash(contents: &[u8]) -> String {
+        hex::encode(Sha256::digest(contents))
+    }
+
+    /// Computes the hash of a file's contents from a reader, as stored in the manifest.
+    pub fn hash_reader<R: Read>(mut reader: R) -> Result<String> {
+        let mut hasher = Sha256::new();
+        std::io::copy(&mut reader, &mut hasher)?;
+        Ok(hex::encode(hasher.finalize()))
+    }
+}
+
+impl ChestCompression {
+    /// Gets the zip file options for writing a file with this compression.
+    fn file_options(&self) -> FileOptions {
+        match self {
+            ChestCompression::Store => {
+                FileOptions::default().compression_method(CompressionMethod::Stored)
+            }
+            ChestCompression::Zstd(level) => FileOptions::default()
+                .compression_method(CompressionMethod::Zstd)
+                .compression_level(Some(*level)),
+        }
+    }
+}
+
+impl CompressionPolicy {
+    /// Extensions of file types that are already compressed, and will not get any smaller
+    /// by compressing them again.
+    const COMPRESSED_EXTENSIONS: &'static [&'static str] = &[
+        "png", "jpg", "jpeg", "gif", "webp", "avif", "ico", "woff", "woff2", "zip", "gz", "bz2",
+        "xz", "zst", "7z", "mp3", "mp4", "ogg", "webm",
+    ];
+
+    /// Create a new compression policy that uses the given compression for all files.
+    pub fn new(default: ChestCompression) -> Self {
+        Self {
+            default,
+            extensions: BTreeMap::new(),
+            sizes: BTreeMap::new(),
+            paths: BTreeMap::new(),
+        }
+    }
+
+    /// Sets the compression to use for files that do not match any other rule.
+    pub fn set_default(&mut self, compression: ChestCompression) {
+        self.default = compression;
+    }
+
+    /// Sets the compression to use for files with the given extension. Extensions are
+    /// matched without regard to case and should not include the leading dot.
+    pub fn set_extension(&mut self, extension: &str, compression: ChestCompression) {
+        self.extensions
+            .insert(extension.to_ascii_lowercase(), compression);
+    }
+
+    /// Sets the compression to use for files that are at least `min_size` bytes in size. If
+    /// more than one size applies to a file, the largest one is used.
+    pub fn set_min_size(&mut self, min_size: u64, compression: ChestCompression) {
+        self.sizes.insert(min_size, compression);
+    }
+
+    /// Sets the compression to use for the file at the given path. This overrides all other
+    /// rules for the file.
+    pub fn set_path(&mut self, path: &str, compression: ChestCompression) {
+        let path = path.strip_prefix('/').unwrap_or(path);
+        self.paths.insert(path.to_string(), compression);
+    }
+
+    /// Gets the compression to use for a file with the given path and size.
+    pub fn compression_for(&self, path: &str, size: u64) -> ChestCompression {
+        let path = path.strip_prefix('/').unwrap_or(path);
+        if let Some(compression) = self.paths.get(path) {
+            return *compression;
+        }
+
+        // Check for a rule for the file's extension
+        let filename = path.rsplit('/').next().unwrap_or(path);
+        if let Some((_, extension)) = filename.rsplit_once('.') {
+            if let Some(compression) = self.extensions.get(&extension.to_ascii_lowercase()) {
+                return *compression;
+            }
+        }
+
+        // Check for a rule for the file's size
+        if let Some((_, compression)) = self.sizes.range(..=size).next_back() {
+            return *compression;
+        }
+
+        self.default
+    }
+}
+
+impl Default for CompressionPolicy {
+    /// The default policy compresses with zstd, as it is much faster than deflate, and stores
+    /// file types that are already compressed.
+    fn default() -> Self {
+        let mut result = Self::new(ChestCompression::Zstd(7));
+        for extension in Self::COMPRESSED_EXTENSIONS {
+            result.set_extension(extension, ChestCompression::Store);
+        }
+        result
+    }
+}
+
+impl ChestFile {
+    /// Gets the uncompressed size of the file.
+    fn size(&self) -> u64 {
+        match self {
+            ChestFile::InMemoryFile(contents) => contents.len() as u64,
+            ChestFile::ZipBackedFile(file) => file.size,
+            ChestFile::DirectoryBackedFile(file_path) => std::fs::metadata(file_path)
+                .map(|metadata| metadata.len())
+                .unwrap_or(0),
+            ChestFile::TempFileBackedFile(file) => file.size,
+        }
+    }
+
+    /// Gets the amount of memory used by the file's contents.
+    fn memory_size(&self) -> u64 {
+        match self {
+            ChestFile::InMemoryFile(contents) => contents.len() as u64,
+            _ => 0,
+        }
+    }
+}
+
+impl ChestDirectoryEntry {
+    /// Gets the amount of memory used by the contents of all files in the entry.
+    fn memory_size(&self) -> u64 {
+        match self {
+            ChestDirectoryEntry::File(file) => file.memory_size(),
+            ChestDirectoryEntry::Directory(directory) => directory.memory_size(),
+        }
+    }
+}
+
+impl ChestDirectory {
+    /// Gets the amount of memory used by the contents of all files in the directory.
+    fn memory_size(&self) -> u64 {
+        self.contents
+            .values()
+            .map(|entry| entry.memory_size())
+            .sum()
+    }
+}
+
+impl TempFileEntry {
+    /// Reads the contents of the file from the temporary file.
+    fn read(&self) -> Result<Vec<u8>> {
+        let mut contents = vec![0; self.size as usize];
+        let mut done = 0;
+        while done < contents.len() {
+            let size = ChestArchiveReader::read_at(
+                &self.file,
+                &mut contents[done..],
+                self.offset + done as u64,
+            )?;
+            if size == 0 {
+                return Err(Error::msg("Temporary file is truncated"));
+            }
+            done += size;
+        }
+        Ok(contents)
+    }
+
+    /// Writes to a file at the given offset without changing any shared state.
+    #[cfg(unix)]
+    fn write_all_at(file: &File, buf: &[u8], offset: u64) -> std::io::Result<()> {
+        std::os::unix::fs::FileExt::write_all_at(file, buf, offset)
+    }
+
+    /// Writes to a file at the given offset without changing any shared state.
+    #[cfg(windows)]
+    fn write_all_at(file: &File, mut buf: &[u8], mut offset: u64) -> std::io::Result<()> {
+        while !buf.is_empty() {
+            let size = std::os::windows::fs::FileExt::seek_write(file, buf, offset)?;
+            if size == 0 {
+                return Err(std::io::ErrorKind::WriteZero.into());
+            }
+            buf = &buf[size..];
+            offset += size as u64;
+        }
+        Ok(())
+    }
+}
+
+impl ZipEntry {
+    /// Reads the contents of the file from the zip archive.
+    fn read(&self) -> Result<Vec<u8>> {
+        let mut existing_zip = self.archive.zip.clone();
+        let mut contents = Vec::new();
+        DeclaredSizeReader::new(existing_zip.by_index(self.index)?).read_to_end(&mut contents)?;
+        Ok(contents)
+    }
+
+    /// Gets the hash of the file's contents. The hash is taken from the manifest of the zip
+    /// archive if it is listed there, otherwise the file is read to compute it.
+    fn hash(&self) -> Result<String> {
+        let mut existing_zip = self.archive.zip.clone();
+        let name = existing_zip.by_index_raw(self.index)?.name().to_string();
+        match self.archive.manifest().files.get(&name) {
+            Some(hash) => Ok(hash.clone()),
+            None => self.compute_hash(),
+        }
+    }
+
+    /// Computes the hash of the file's contents by reading the file, without trusting the
+    /// manifest of the zip archive.
+    fn compute_hash(&self) -> Result<String> {
+        let mut existing_zip = self.archive.zip.clone();
+        let file = existing_zip.by_index(self.index)?;
+        ChestManifest::hash_reader(DeclaredSizeReader::new(file))
+    }
+}
+
+impl ChestArchive {
+    /// Gets the manifest stored in the zip archive. The manifest is read the first time it
+    /// is needed. If the archive does not have a valid manifest, the manifest is empty.
+    fn manifest(&self) -> &ChestManifest {
+        self.manifest
+            .get_or_init(|| ChestManifest::read_from_zip(&mut self.zip.clone()).unwrap_or_default())
+    }
+}
+
+impl ChestArchiveReader {
+    /// Create a reader for a chest archive on disk.
+    fn new(file: File) -> Result<Self> {
+        let len = file.metadata()?.len();
+        Ok(Self {
+            file: Arc::new(file),
+            len,
+            position: 0,
+            buffer: Vec::new(),
+            buffer_start: 0,
+        })
+    }
+
+    /// Reads from a file at the given offset without changing any shared state.
+    #[cfg(unix)]
+    fn read_at(file: &File, buf: &mut [u8], offset: u64) -> std::io::Result<usize> {
+        std::os::unix::fs::FileExt::read_at(file, buf, offset)
+    }
+
+    /// Reads from a file at the given offset without changing any shared state.
+    #[cfg(windows)]
+    fn read_at(file: &File, buf: &mut [u8], offset: u64) -> std::io::Result<usize> {
+        std::os::windows::fs::FileExt::seek_read(file, buf, offset)
+    }
+}
+
+impl<'a> DeclaredSizeReader<ZipFile<'a>> {
+    /// Create a reader for a file within a chest archive.
+    fn new(file: ZipFile<'a>) -> Self {
+        Self {
+            name: file.name().to_string(),
+            remaining: file.size(),
+            inner: file,
+        }
+    }
+}
+
+impl<R: Read> Read for DeclaredSizeReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        // Always allow reading one byte past the declared size to detect extra data
+        let limit = buf.len().min(self.remaining.saturating_add(1) as usize);
+        let size = self.inner.read(&mut buf[..limit])?;
+        if size as u64 > self.remaining {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("File '{}' is larger than its declared size", self.name),
+            ));
+        }
+        if size == 0 && self.remaining > 0 && !buf.is_empty() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::UnexpectedEof,
+                format!("File '{}' is smaller than its declared size", self.name),
+            ));
+        }
+        self.remaining -= size as u64;
+        Ok(size)
+    }
+}
+
+impl Clone for ChestArchiveReader {
+    fn clone(&self) -> Self {
+        // The buffer is not shared, each clone starts with an empty buffer
+        Self {
+            file: self.file.clone(),
+            len: self.len,
+            position: self.position,
+            buffer: Vec::new(),
+            buffer_start: 0,
+        }
+    }
+}
+
+impl Read for ChestArchiveReader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        // Check for data in the buffer at the current position
+        let buffer_end = self.buffer_start + self.buffer.len() as u64;
+        if self.position < self.buffer_start || self.position >= buffer_end {
+            if buf.len() >= ARCHIVE_READ_BUFFER_SIZE {
+                // Large reads bypass the buffer entirely
+                let size = Self::read_at(&self.file, buf, self.position)?;
+                self.position += size as u64;
+                return Ok(size);
+            }
+
+            // Refill the buffer at the current position
+            self.buffer.resize(ARCHIVE_READ_BUFFER_SIZE, 0);
+            let size = match Self::read_at(&self.file, &mut self.buffer, self.position) {
+                Ok(size) => size,
+                Err(error) => {
+                    self.buffer.clear();
+                    return Err(error);
+                }
+            };
+            self.buffer.truncate(size);
+            self.buffer_start = self.position;
+            if size == 0 {
+                return Ok(0);
+            }
+        }
+
+        // Copy out as much as possible from the buffer
+        let offset = (self.position - self.buffer_start) as usize;
+        let size = buf.len().min(self.buffer.len() - offset);
+        buf[..size].copy_from_slice(&self.buffer[offset..offset + size]);
+        self.position += size as u64;
+        Ok(size)
+    }
+}
+
+impl Seek for ChestArchiveReader {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        let position = match pos {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::End(offset) => self.len.checked_add_signed(offset),
+            SeekFrom::Current(offset) => self.position.checked_add_signed(offset),
+        };
+        match position {
+            Some(position) => {
+                self.position = position;
+                Ok(position)
+            }
+            None => Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "Invalid seek to a negative position",
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::NamedTempFile;
+    use zip::result::ZipResult;
+
+    /// Builds a zip archive in memory.
+    fn build_zip<F>(build: F) -> Vec<u8>
+    where
+        F: FnOnce(&mut ZipWriter<Cursor<Vec<u8>>>, FileOptions) -> ZipResult<()>,
+    {
+        let mut zip = ZipWriter::new(Cursor::new(Vec::new()));
+        build(&mut zip, FileOptions::default()).unwrap();
+        zip.finish().unwrap().into_inner()
+    }
+
+    /// Writes a zip archive to a temporary file and opens it as a chest. The temporary file
+    /// is returned so that it outlives the chest.
+    fn open_zip(data: &[u8]) -> (NamedTempFile, Result<Chest>) {
+        let mut file = NamedTempFile::new().unwrap();
+        file.write_all(data).unwrap();
+        file.flush().unwrap();
+        let chest = Chest::open(file.path());
+        (file, chest)
+    }
+
+    /// Opens a zip archive as a chest and returns the error message it was rejected with.
+    fn open_error(data: &[u8]) -> String {
+        match open_zip(data).1 {
+            Ok(_) => panic!("Chest was opened"),
+            Err(error) => format!("{:#}", error),
+        }
+    }
+
+    /// Overwrites the uncompressed size of the first file in a zip archive, in the header
+    /// starting with the given signature at the given offset.
+    fn set_declared_size(data: &mut [u8], signature: &[u8; 4], offset: usize, size: u32) {
+        let start = data
+            .windows(4)
+            .position(|window| window == signature)
+            .unwrap();
+        data[start + offset..start + offset + 4].copy_from_slice(&size.to_le_bytes());
+    }
+
+    fn set_local_size(data: &mut [u8], size: u32) {
+        set_declared_size(data, b"PK\x03\x04", 22, size);
+    }
+
+    fn set_central_size(data: &mut [u8], size: u32) {
+        set_declared_size(data, b"PK\x01\x02", 24, size);
+    }
+
+    #[test]
+    fn open_valid_archive() {
+        let data = build_zip(|zip, options| {
+            zip.add_directory("dir", options)?;
+            zip.start_file("dir/file.html", options)?;
+            zip.write_all(b"contents")?;
+            Ok(())
+        });
+        let (_file, chest) = open_zip(&data);
+        assert_eq!(chest.unwrap().read("dir/file.html").unwrap(), b"contents");
+    }
+
+    #[test]
+    fn reject_parent_components() {
+        let data = build_zip(|zip, options| zip.start_file("dir/../../escape.html", options));
+        assert_eq!(
+            open_error(&data),
+            "Invalid path 'dir/../../escape.html' in chest: Path components cannot be '.' or '..'"
+        );
+    }
+
+    #[test]
+    fn reject_current_components() {
+        let data = build_zip(|zip, options| zip.start_file("./file.html", options));
+        assert_eq!(
+            open_error(&data),
+            "Invalid path './file.html' in chest: Path components cannot be '.' or '..'"
+        );
+    }
+
+    #[test]
+    fn reject_absolute_paths() {
+        let data = build_zip(|zip, options| zip.start_file("/etc/escape.html", options));
+        assert_eq!(
+            open_error(&data),
+            "Entry '/etc/escape.html' has an absolute path, which is not allowed in chests"
+        );
+    }
+
+    #[test]
+    fn reject_symbolic_links() {
+        let data = build_zip(|zip, options| zip.add_symlink("link.html", "/etc/passwd", options));
+        assert_eq!(
+            open_error(&data),
+            "Entry 'link.html' is a symbolic link, which is not allowed in chests"
+        );
+    }
+
+    #[test]
+    fn reject_files_larger_than_declared() {
+        let mut data = build_zip(|zip, options| {
+            zip.start_file("file.html", options)?;
+            zip.write_all(&[b'a'; 4096])?;
+            Ok(())
+        });
+        set_local_size(&mut data, 16);
+        set_central_size(&mut data, 16);
+
+        // The declared size is within the limits, so the file is only rejected when read
+        let (_file, chest) = open_zip(&data);
+        let chest = chest.unwrap();
+        assert_eq!(
+            format!("{:#}", chest.read("file.html").unwrap_err()),
+            "File 'file.html' is larger than its declared size"
+        );
+        assert!(chest.hash("file.html").is_err());
+    }
+
+    #[test]
+    fn reject_high_compression_ratio() {
+        let mut data = build_zip(|zip, options| {
+            zip.start_file("file.html", options)?;
+            zip.write_all(&[0; 1024])?;
+            Ok(())
+        });
+        set_central_size(&mut data, 512 * 1024 * 1024);
+        assert!(open_error(&data).starts_with(
+            "File 'file.html' has a suspicious compression ratio (536870912 bytes compressed to"
+        ));
+    }
+
+    #[test]
+    fn reject_too_many_entries() {
+        let data = build_zip(|zip, options| {
+            for index in 0..4 {
+                zip.start_file(index.to_string(), options)?;
+            }
+            Ok(())
+        });
+        let (file, _) = open_zip(&data);
+        assert!(Chest::open_archive(file.path(), 4).is_ok());
+        assert_eq!(
+            format!("{:#}", Chest::open_archive(file.path(), 3).unwrap_err()),
+            "Chest has too many entries (4, the limit is 3)"
+        );
+    }
+
+    #[test]
+    fn failed_merge_leaves_chest_unchanged() {
+        let mut chest = Chest::new();
+        chest.write("docs/api/index.html", b"existing").unwrap();
+        let mut other = Chest::new();
+        other.write("api/index.html", b"new").unwrap();
+        other.write("guide.html", b"new").unwrap();
+
+        let error = chest.merge(other, "docs").unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Cannot merge chests, 'docs/api/index.html' exists in both chests"
+        );
+        assert!(!chest.contains("docs/guide.html"));
+        assert_eq!(chest.read("docs/api/index.html").unwrap(), b"existing");
+
+        let mut other = Chest::new();
+        other.write("guide.html", b"new").unwrap();
+        let error = chest.merge(other, "docs/api/index.html/more").unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Cannot create directory because a file already exists there"
+        );
+        assert_eq!(chest.list_dir("docs/api").unwrap().len(), 1);
+    }
+
+    #[test]
+    fn failed_rename_leaves_no_directories() {
+        let mut chest = Chest::new();
+        chest.write("file.html", b"contents").unwrap();
+
+        assert!(chest.rename("file.html", "new/dir/bad:name").is_err());
+        assert!(chest.rename("file.html", "file.html/new/name").is_err());
+        assert_eq!(chest.list_dir("").unwrap().len(), 1);
+        assert_eq!(chest.read("file.html").unwrap(), b"contents");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn extract_does_not_follow_symbolic_links() {
+        let outside = tempfile::tempdir().unwrap();
+        let target = tempfile::tempdir().unwrap();
+        std::os::unix::fs::symlink(outside.path(), target.path().join("dir")).unwrap();
+
+        let mut chest = Chest::new();
+        chest.write("dir/file.html", b"contents").unwrap();
+        let error = chest.extract(target.path(), |_| ()).unwrap_err();
+        assert!(error
+            .to_string()
+            .ends_with("is a symbolic link, refusing to extract through it"));
+        assert!(!outside.path().join("file.html").exists());
+    }
+}
@@ -51,6 +51,7 @@ pub struct Container {
     step_progress: BTreeMap<usize, ContainerProgressType>,
     first_apt: bool,
     image: Option<String>,
+    memory_budget: Option<u64>,
 }
 
 impl Container {
@@ -63,6 +64,7 @@ impl Container {
             step_progress: BTreeMap::new(),
             first_apt: true,
             image: None,
+            memory_budget: None,
         }
     }
 
@@ -205,6 +207,7 @@ impl Container {
             // Command is to get an archive from the container. Read the archive from stdout and
             // decode it as a tar archive.
             let mut result = Chest::new();
+            result.set_memory_budget(self.memory_budget);
             let mut tar = tar::Archive::new(&mut stdout);
             for entry in tar.entries()? {
                 let mut entry = entry?;
@@ -406,6 +409,12 @@ impl Container {
         result
     }
 
+    /// Sets the maximum amount of file contents to keep in memory for chests returned by
+    /// `get_archive`. See [Chest::set_memory_budget].
+    pub fn set_memory_budget(&mut self, budget: Option<u64>) {
+        self.memory_budget = budget;
+    }
+
     /// Get a tar archive of a path inside the built image. The image must first be built with `build`.
     pub fn get_archive(&self, path: &str) -> Result<Chest> {
         // Get the image identifier
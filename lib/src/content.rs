@@ -1,14 +1,17 @@
-use crate::chest::Chest;
+use crate::chest::{Chest, ChestManifest};
 use crate::db::{SearchParameters, Theme};
-use anyhow::Result;
+use anyhow::{anyhow, Error, Result};
+use bincode::Options;
 use btree_range_map::RangeMap;
 use code_fuzzy_match::FuzzyMatcher;
 use rayon::prelude::*;
-use serde::{Deserialize, Serialize};
+use serde::de::{IgnoredAny, MapAccess, Visitor};
+use serde::{Deserialize, Deserializer, Serialize};
 use std::borrow::Cow;
 use std::cmp::Ordering;
-use std::collections::BTreeSet;
+use std::collections::{BTreeMap, BTreeSet};
 use std::fmt::Display;
+use std::io::Read;
 use std::ops::Range;
 use uuid::Uuid;
 
@@ -16,9 +19,38 @@ use uuid::Uuid;
 /// a match of a one character query at the start of a word.
 const MIN_SEARCH_SCORE: usize = 9;
 
+/// Path of the chest contents within a chest
+pub const CONTENTS_PATH: &'static str = "_chest_contents.json";
+
+/// Version of the chest contents format written by this version of docdelve. This must be
+/// increased whenever the serialized form of the chest contents changes, along with adding
+/// a migration to [FORMAT_MIGRATIONS].
+pub const CHEST_FORMAT_VERSION: u32 = 1;
+
+/// Path of the binary index of the chest contents within a chest
+pub const INDEX_PATH: &'static str = "_chest_index.bin";
+
+/// Magic bytes at the start of the binary index of the chest contents
+const INDEX_MAGIC: &[u8; 8] = b"DDINDEX\0";
+
+/// Version of the layout of the binary index. This must be increased whenever the indexed
+/// form of the chest contents changes.
+const INDEX_FORMAT_VERSION: u32 = 2;
+
+/// Migrations between consecutive versions of the chest contents format, in order.
+const FORMAT_MIGRATIONS: &[FormatMigration] = &[FormatMigration {
+    version: 1,
+    upgrade: upgrade_format_v1,
+    downgrade: downgrade_format_v1,
+}];
+
 /// Information about a chest.
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Clone)]
 pub struct ChestInfo {
+    /// Version of the chest contents format. Chests created before the format was
+    /// versioned do not have a format version and are read as version 0.
+    #[serde(default)]
+    pub format_version: u32,
     pub category_tag: String,
     pub identifier: String,
     pub category_tag_aliases: Vec<String>,
@@ -38,18 +70,21 @@ pub struct ChestContents {
 }
 
 /// Chest contents optimized for searching.
+#[derive(Serialize, Deserialize)]
 pub struct IndexedChestContents {
     pub info: ChestInfo,
     items: Vec<IndexedChestItem>,
     root_item_ids: Vec<IndexedChestItemId>,
+    /// Items that reference each URL, in the order that they appear in the contents
+    url_map: BTreeMap<String, Vec<IndexedChestItemId>>,
 }
 
 /// Reference to an item in [IndexedChestContents].
-#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash)]
 pub struct IndexedChestItemId(usize);
 
 /// List of adjustments to apply for a given theme.
-#[derive(Serialize, Deserialize, Default)]
+#[derive(Serialize, Deserialize, Clone, Default)]
 pub struct ThemeAdjustment {
     pub file_replacements: Vec<FileReplacementRule>,
 }
@@ -58,7 +93,7 @@ pub struct ThemeAdjustment {
 /// matched as whole path elements at the end of the path, unless it starts with a '/'.
 /// The `replacement` will be used to replace the matched path elements, or replaces the
 /// entire path if it starts with a '/'.
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Clone)]
 pub struct FileReplacementRule {
     pub pattern: String,
     pub replacement: String,
@@ -74,6 +109,7 @@ pub enum ChestItem {
 }
 
 /// An item contained in a chest in indexed form, with the path to the item.
+#[derive(Serialize, Deserialize)]
 pub struct IndexedChestItem {
     parent_path: Vec<IndexedChestItemId>,
     children: Range<usize>,
@@ -82,6 +118,7 @@ pub struct IndexedChestItem {
 
 /// A single item contained in a chest in indexed form. Any contained items will be
 /// referenced using an [IndexedChestItemId].
+#[derive(Serialize, Deserialize)]
 pub enum IndexedChestItemData {
     Module(IndexedModule),
     Group(IndexedGroup),
@@ -108,6 +145,7 @@ pub struct Module {
 
 /// A module contained within a chest in indexed form. Any contained items will be referenced
 /// using an [IndexedChestItemId].
+#[derive(Serialize, Deserialize)]
 pub struct IndexedModule {
     pub info: ModuleInfo,
     contents: Vec<IndexedChestItemId>,
@@ -130,6 +168,7 @@ pub struct Group {
 
 /// A named group of items contained within a chest in indexed form. Any contained items will be
 /// referenced using an [IndexedChestItemId].
+#[derive(Serialize, Deserialize)]
 pub struct IndexedGroup {
     pub info: GroupInfo,
     contents: Vec<IndexedChestItemId>,
@@ -144,6 +183,7 @@ pub struct Page {
 }
 
 /// A text page contained within a chest in indexed form. Also contains a table of contents.
+#[derive(Serialize, Deserialize)]
 pub struct IndexedPage {
     pub info: Page,
     contents: Vec<IndexedChestItemId>,
@@ -172,6 +212,7 @@ pub struct PageLink {
 }
 
 /// An item on a page contained within a chest in indexed form.
+#[derive(Serialize, Deserialize)]
 pub struct IndexedPageItem {
     pub page: IndexedChestItemId,
     pub title: String,
@@ -200,6 +241,7 @@ pub struct Object {
 
 /// A programming language object contained within a chest in indexed form. Any contained items
 /// will be referenced using an [IndexedChestItemId].
+#[derive(Serialize, Deserialize)]
 pub struct IndexedObject {
     pub info: ObjectInfo,
     contents: Vec<IndexedChestItemId>,
@@ -272,12 +314,50 @@ struct IndexedChestSearchResult {
     pub score: usize,
 }
 
+/// Migration of the serialized chest contents between a format version and the version
+/// before it. The migrations operate on the JSON representation so that they do not depend
+/// on the current shape of the content types.
+struct FormatMigration {
+    /// Format version that this migration upgrades to
+    version: u32,
+    /// Converts contents from `version - 1` to `version`. When only the chest information is
+    /// read, the contents are given without their `items` field.
+    upgrade: fn(&mut serde_json::Value) -> Result<()>,
+    /// Converts contents from `version` to `version - 1`
+    downgrade: fn(&mut serde_json::Value) -> Result<()>,
+}
+
+/// Serialized chest contents without the items, so that the chest information can be read
+/// without building the items.
+struct ChestInfoFields(serde_json::Map<String, serde_json::Value>);
+
 /// List of items contained within an item.
 enum ItemContents {
     ChestItems(Vec<ChestItem>),
     PageItems(Vec<PageItem>),
 }
 
+impl ChestInfo {
+    /// Performs per-theme path transformation according to the chest configuration.
+    pub fn transform_path_for_theme<'a>(&'a self, path: &'a str, theme: Theme) -> Cow<str> {
+        if let Some(adjustment) = match theme {
+            Theme::Light => &self.light_mode,
+            Theme::Dark => &self.dark_mode,
+        } {
+            for replacement in &adjustment.file_replacements {
+                if let Some(transform) =
+                    Chest::transform_path(path, &replacement.pattern, &replacement.replacement)
+                {
+                    return Cow::Owned(transform);
+                }
+            }
+            Cow::Borrowed(path)
+        } else {
+            Cow::Borrowed(path)
+        }
+    }
+}
+
 impl ChestContents {
     /// Create a new empty chest.
     pub fn new(
@@ -291,6 +371,7 @@ impl ChestContents {
     ) -> Self {
         Self {
             info: ChestInfo {
+                format_version: CHEST_FORMAT_VERSION,
                 category_tag: category_tag.to_string(),
                 identifier: Uuid::new_v4().simple().to_string(),
                 category_tag_aliases: category_tag_aliases.iter().map(|s| s.to_string()).collect(),
@@ -304,18 +385,107 @@ impl ChestContents {
         }
     }
 
-    /// Read the contents of a chest from a chest.
+    /// Read the contents of a chest from a chest. Contents in older formats are upgraded to
+    /// the current format. Chests with a newer format than this version of docdelve supports
+    /// are rejected with an error.
     pub fn read_from_chest(chest: &Chest) -> Result<Self> {
-        let contents = String::from_utf8(chest.read("_chest_contents.json")?)?;
-        let result: ChestContents = serde_json::from_str(&contents)?;
-        Ok(result)
+        let mut contents: serde_json::Value = serde_json::from_slice(&chest.read(CONTENTS_PATH)?)?;
+        Self::upgrade(&mut contents)?;
+        serde_json::from_value(contents)
+            .map_err(|error| anyhow!("Chest contents are not valid: {}", error))
+    }
+
+    /// Read only the chest information from the contents of a chest. The items are skipped
+    /// while parsing, so they are not built.
+    pub fn read_info_from_chest(chest: &Chest) -> Result<ChestInfo> {
+        let fields: ChestInfoFields = serde_json::from_slice(&chest.read(CONTENTS_PATH)?)?;
+        let mut contents = serde_json::Value::Object(fields.0);
+        Self::upgrade(&mut contents)?;
+        serde_json::from_value(contents)
+            .map_err(|error| anyhow!("Chest contents are not valid: {}", error))
+    }
+
+    /// Upgrades serialized chest contents to the current format. Chests with a newer format
+    /// than this version of docdelve supports are rejected with an error.
+    fn upgrade(contents: &mut serde_json::Value) -> Result<()> {
+        let version = Self::format_version(contents)?;
+        if version > CHEST_FORMAT_VERSION {
+            return Err(anyhow!(
+                "Chest requires a newer version of docdelve (chest format version {}, \
+                supported format version {})",
+                version,
+                CHEST_FORMAT_VERSION
+            ));
+        }
+
+        for migration in FORMAT_MIGRATIONS
+            .iter()
+            .filter(|migration| migration.version > version)
+        {
+            (migration.upgrade)(contents)?;
+            Self::set_format_version(contents, migration.version);
+        }
+        Ok(())
     }
 
     /// Writes the chest contents to a chest.
     pub fn write_to_chest(&self, chest: &mut Chest) -> Result<()> {
-        let contents = serde_json::to_string(self)?;
-        chest.write("_chest_contents.json", contents.as_bytes())?;
-        Ok(())
+        self.write_to_chest_with_format(chest, CHEST_FORMAT_VERSION)
+    }
+
+    /// Writes the chest contents to a chest in an older format, so that the chest can be
+    /// read by older versions of docdelve. Information that can't be represented in the
+    /// older format is dropped.
+    pub fn write_to_chest_with_format(&self, chest: &mut Chest, version: u32) -> Result<()> {
+        if version > CHEST_FORMAT_VERSION {
+            return Err(anyhow!(
+                "Chest format version {} is not supported, the newest supported format \
+                version is {}",
+                version,
+                CHEST_FORMAT_VERSION
+            ));
+        }
+
+        let mut contents = serde_json::to_value(self)?;
+        Self::set_format_version(&mut contents, CHEST_FORMAT_VERSION);
+        let indexed = ChestContents::deserialize(&contents)?.to_indexed();
+        for migration in FORMAT_MIGRATIONS
+            .iter()
+            .rev()
+            .filter(|migration| migration.version > version)
+        {
+            (migration.downgrade)(&mut contents)?;
+            Self::set_format_version(&mut contents, migration.version - 1);
+        }
+
+        // Write the binary index along with the contents, so that readers don't need to parse
+        // and index the contents themselves
+        let contents = serde_json::to_string(&contents)?;
+        chest.write(CONTENTS_PATH, contents.as_bytes())?;
+        indexed.write_index(chest, &ChestManifest::hash(contents.as_bytes()))
+    }
+
+    /// Gets the format version of serialized chest contents.
+    fn format_version(contents: &serde_json::Value) -> Result<u32> {
+        match contents.get("format_version") {
+            Some(version) => version
+                .as_u64()
+                .and_then(|version| u32::try_from(version).ok())
+                .ok_or_else(|| Error::msg("Chest format version is not valid")),
+            None => Ok(0),
+        }
+    }
+
+    /// Sets the format version of serialized chest contents. Version 0 predates the format
+    /// version, so it is stored without one.
+    fn set_format_version(contents: &mut serde_json::Value, version: u32) {
+        if let Some(contents) = contents.as_object_mut() {
+            if version == 0 {
+                contents.remove("format_version");
+            } else {
+                contents.insert("format_version".to_string(), version.into());
+            }
+        }
     }
 
     /// Converts chest contents into indexed form.
@@ -323,11 +493,14 @@ impl ChestContents {
         let mut items = Vec::new();
         let mut path = Vec::new();
         let root_item_ids = Self::indexed_contents(self.items, &mut items, &mut path);
-        IndexedChestContents {
+        let mut result = IndexedChestContents {
             info: self.info,
             items,
             root_item_ids,
-        }
+            url_map: BTreeMap::new(),
+        };
+        result.url_map = result.url_map();
+        result
     }
 
     /// Converts a list of chest items into indexed form.
@@ -464,6 +637,157 @@ impl ChestContents {
 }
 
 impl IndexedChestContents {
+    /// Reads the contents of a chest in indexed form. The binary index stored in the chest is
+    /// used if it is up to date with the chest contents, otherwise the chest contents are read
+    /// and indexed. The index is read and deserialized in full, which avoids parsing the JSON
+    /// contents but still reads every item.
+    pub fn read_from_chest(chest: &Chest) -> Result<Self> {
+        if chest.contains(INDEX_PATH) {
+            if let Ok(Some(indexed)) = Self::read_index(chest) {
+                return Ok(indexed);
+            }
+        }
+        Ok(ChestContents::read_from_chest(chest)?.to_indexed())
+    }
+
+    /// Reads the chest information from the binary index of a chest, without reading the
+    /// items. Returns `None` if the chest does not have an index that is up to date with the
+    /// chest contents.
+    pub fn read_info_from_chest(chest: &Chest) -> Result<Option<ChestInfo>> {
+        if !chest.contains(INDEX_PATH) {
+            return Ok(None);
+        }
+        let limit = chest.size(INDEX_PATH)?;
+        chest.read_with(INDEX_PATH, |reader| {
+            Self::read_chest_index_header(chest, reader, limit)
+        })
+    }
+
+    /// Reads the binary index from a chest. Returns `None` if the index was written for a
+    /// different chest format or for different chest contents.
+    fn read_index(chest: &Chest) -> Result<Option<Self>> {
+        let limit = chest.size(INDEX_PATH)?;
+        chest.read_with(INDEX_PATH, |reader| {
+            match Self::read_chest_index_header(chest, reader, limit)? {
+                Some(info) => Ok(Some(Self::read_index_items(reader, limit, info)?)),
+                None => Ok(None),
+            }
+        })
+    }
+
+    /// Reads the header of the binary index stored in a chest. Returns `None` if the index
+    /// was written for a different chest format or for different chest contents.
+    ///
+    /// The index is compared against the hash of the chest contents given by [Chest::hash],
+    /// which for zip archives comes from the archive's manifest rather than the contents
+    /// themselves. An archive whose manifest doesn't match its contents can therefore have its
+    /// index used in place of the contents. Use [Chest::verify] to detect such archives.
+    fn read_chest_index_header(
+        chest: &Chest,
+        reader: &mut dyn Read,
+        limit: u64,
+    ) -> Result<Option<ChestInfo>> {
+        match Self::read_index_header(reader, limit)? {
+            Some((contents_hash, info)) if contents_hash == chest.hash(CONTENTS_PATH)? => {
+                Ok(Some(info))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    /// Reads the header of a binary index, which ends with the chest information. Returns
+    /// the hash of the source that the index was created from along with the chest
+    /// information, or `None` if the index was written for a different chest format.
+    pub(crate) fn read_index_header(
+        reader: &mut dyn Read,
+        limit: u64,
+    ) -> Result<Option<(String, ChestInfo)>> {
+        let options = Self::index_options(limit);
+        let mut magic = [0; INDEX_MAGIC.len()];
+        reader.read_exact(&mut magic)?;
+        if &magic != INDEX_MAGIC {
+            return Err(Error::msg("Chest index is not valid"));
+        }
+
+        let index_version: u32 = options.deserialize_from(&mut *reader)?;
+        let format_version: u32 = options.deserialize_from(&mut *reader)?;
+        if index_version != INDEX_FORMAT_VERSION || format_version != CHEST_FORMAT_VERSION {
+            return Ok(None);
+        }
+        let source_hash = options.deserialize_from(&mut *reader)?;
+        let info = options.deserialize_from(reader)?;
+        Ok(Some((source_hash, info)))
+    }
+
+    /// Reads the items of a binary index, which follow the header. The index may come from
+    /// an untrusted chest, so an error is returned if any item reference is out of bounds.
+    pub(crate) fn read_index_items(
+        reader: &mut dyn Read,
+        limit: u64,
+        info: ChestInfo,
+    ) -> Result<Self> {
+        let (items, root_item_ids, url_map) =
+            Self::index_options(limit).deserialize_from(reader)?;
+        let contents = Self {
+            info,
+            items,
+            root_item_ids,
+            url_map,
+        };
+        if !contents.is_valid() {
+            return Err(Error::msg("Chest index is not valid"));
+        }
+        Ok(contents)
+    }
+
+    /// Checks that every item reference and range of children in the contents is within the
+    /// list of items.
+    fn is_valid(&self) -> bool {
+        let count = self.items.len();
+        let valid_id = |id: &IndexedChestItemId| id.0 < count;
+        self.root_item_ids.iter().all(valid_id)
+            && self.url_map.values().flatten().all(valid_id)
+            && self.items.iter().all(|item| {
+                item.children.start <= item.children.end
+                    && item.children.end <= count
+                    && item.parent_path.iter().all(valid_id)
+                    && item.content_ids().iter().all(valid_id)
+                    && match &item.data {
+                        IndexedChestItemData::PageItem(page_item) => valid_id(&page_item.page),
+                        _ => true,
+                    }
+            })
+    }
+
+    /// Writes the binary index to a chest. The hash of the chest contents is stored in the
+    /// index, so that an outdated index can be detected.
+    fn write_index(&self, chest: &mut Chest, contents_hash: &str) -> Result<()> {
+        chest.write(INDEX_PATH, &self.to_index(contents_hash)?)
+    }
+
+    /// Serializes the contents into a binary index, along with the hash of the source that
+    /// the contents were created from. The chest information is written before the items, so
+    /// that it can be read without reading the entire index.
+    pub(crate) fn to_index(&self, source_hash: &str) -> Result<Vec<u8>> {
+        let options = Self::index_options(u64::MAX);
+        let mut index = INDEX_MAGIC.to_vec();
+        options.serialize_into(&mut index, &INDEX_FORMAT_VERSION)?;
+        options.serialize_into(&mut index, &CHEST_FORMAT_VERSION)?;
+        options.serialize_into(&mut index, source_hash)?;
+        options.serialize_into(&mut index, &self.info)?;
+        options.serialize_into(
+            &mut index,
+            &(&self.items, &self.root_item_ids, &self.url_map),
+        )?;
+        Ok(index)
+    }
+
+    /// Gets the serialization options for the binary index. Reads are limited to the size
+    /// of the index, so that a corrupted index can't cause large allocations.
+    fn index_options(limit: u64) -> impl Options + Copy {
+        bincode::DefaultOptions::new().with_limit(limit)
+    }
+
     /// Gets chest items by path.
     pub fn get(&self, path: &ChestPath) -> Vec<&IndexedChestItem> {
         self.get_ids(path)
@@ -665,21 +989,7 @@ impl IndexedChestContents {
 
     /// Performs per-theme path transformation according to the chest configuration.
     pub fn transform_path_for_theme<'a>(&'a self, path: &'a str, theme: Theme) -> Cow<str> {
-        if let Some(adjustment) = match theme {
-            Theme::Light => &self.info.light_mode,
-            Theme::Dark => &self.info.dark_mode,
-        } {
-            for replacement in &adjustment.file_replacements {
-                if let Some(transform) =
-                    Chest::transform_path(path, &replacement.pattern, &replacement.replacement)
-                {
-                    return Cow::Owned(transform);
-                }
-            }
-            Cow::Borrowed(path)
-        } else {
-            Cow::Borrowed(path)
-        }
+        self.info.transform_path_for_theme(path, theme)
     }
 
     fn look_for_path<F>(
@@ -706,8 +1016,7 @@ impl IndexedChestContents {
         };
 
         // Get the list of possible paths to this chest
-        let mut possible_items = Vec::new();
-        self.search_for_url(&mut possible_items, &self.root_item_ids, url);
+        let mut possible_items = self.url_map.get(url).cloned().unwrap_or_default();
         possible_items.sort_by(|a, b| self.compare_item_paths(*b, *a));
 
         // If there is a path hint, check to see if one of the possible paths is a parent of
@@ -740,19 +1049,25 @@ impl IndexedChestContents {
         self.look_for_path(url, hint_path, |item_id| self.page_path_for_id(item_id))
     }
 
-    /// Recursively search a set of items for a given URL.
-    fn search_for_url(
+    /// Builds the map from URLs to the items that reference them.
+    fn url_map(&self) -> BTreeMap<String, Vec<IndexedChestItemId>> {
+        let mut result = BTreeMap::new();
+        self.add_urls_to_map(&mut result, &self.root_item_ids);
+        result
+    }
+
+    /// Recursively adds the URLs of a set of items to a URL map.
+    fn add_urls_to_map(
         &self,
-        results: &mut Vec<IndexedChestItemId>,
+        url_map: &mut BTreeMap<String, Vec<IndexedChestItemId>>,
         items: &[IndexedChestItemId],
-        url: &str,
     ) {
         for item_id in items.iter() {
             if let Some(item) = self.get_by_id(*item_id) {
-                if item.url() == Some(url) {
-                    results.push(*item_id);
+                if let Some(url) = item.url() {
+                    url_map.entry(url.to_string()).or_default().push(*item_id);
                 }
-                self.search_for_url(results, item.content_ids(), url);
+                self.add_urls_to_map(url_map, item.content_ids());
             }
         }
     }
@@ -1036,3 +1351,49 @@ impl Display for ChestPath {
         }
     }
 }
+
+impl<'de> Deserialize<'de> for ChestInfoFields {
+    fn deserialize<D>(deserializer: D) -> std::result::Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        struct FieldsVisitor;
+
+        impl<'de> Visitor<'de> for FieldsVisitor {
+            type Value = ChestInfoFields;
+
+            fn expecting(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                write!(f, "chest contents")
+            }
+
+            fn visit_map<A>(self, mut map: A) -> std::result::Result<Self::Value, A::Error>
+            where
+                A: MapAccess<'de>,
+            {
+                let mut fields = serde_json::Map::new();
+                while let Some(key) = map.next_key::<String>()? {
+                    if key == "items" {
+                        map.next_value::<IgnoredAny>()?;
+                    } else {
+                        fields.insert(key, map.next_value()?);
+                    }
+                }
+                Ok(ChestInfoFields(fields))
+            }
+        }
+
+        deserializer.deserialize_map(FieldsVisitor)
+    }
+}
+
+/// Upgrades chest contents to format version 1. Version 1 only introduced the format
+/// version itself.
+fn upgrade_format_v1(_contents: &mut serde_json::Value) -> Result<()> {
+    Ok(())
+}
+
+/// Downgrades chest contents from format version 1. Version 1 only introduced the format
+/// version itself.
+fn downgrade_format_v1(_contents: &mut serde_json::Value) -> Result<()> {
+    Ok(())
+}
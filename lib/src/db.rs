@@ -1,33 +1,159 @@
+use crate::cache::ContentsCache;
 use crate::chest::{Chest, ChestListEntry};
 use crate::content::{
-    ChestContents, ChestPath, IndexedChestContents, IndexedChestItem, IndexedChestItemData,
-    PageItem,
+    ChestContents, ChestInfo, ChestPath, IndexedChestContents, IndexedChestItem,
+    IndexedChestItemData, PageItem, CONTENTS_PATH,
 };
+use crate::progress::ProgressEvent;
+use crate::signature::{ChestTrust, TrustStore};
+use crate::version::{Version, VersionSelector};
 use anyhow::{anyhow, Result};
 use directories::ProjectDirs;
 use rayon::prelude::*;
-use std::cmp::Ordering;
-use std::collections::BTreeMap;
-use std::path::PathBuf;
+use std::cmp::{Ordering, Reverse};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::{File, OpenOptions};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering as AtomicOrdering};
+use std::sync::{OnceLock, RwLock};
+use std::time::SystemTime;
+use tempfile::NamedTempFile;
+
+/// Prefix of the temporary files that chests are copied to while they are being installed
+pub(crate) const INSTALL_TEMP_PREFIX: &'static str = ".install-";
+
+/// Suffix of the temporary files that chests are copied to while they are being installed
+pub(crate) const INSTALL_TEMP_SUFFIX: &'static str = ".tmp";
 
 /// Database of all available chests.
 pub struct Database {
-    data_path: PathBuf,
+    paths: Option<DatabasePaths>,
+    trusted_keys: TrustStore,
+    cache: Option<ContentsCache>,
     identifiers: BTreeMap<String, LoadedChest>,
     tags: BTreeMap<String, TagVersions>,
+    /// Lowercase tags and tag aliases, mapped to the tag they refer to
+    aliases: BTreeMap<String, String>,
+    diagnostics: Vec<ChestDiagnostic>,
+    max_loaded_chests: Option<usize>,
+    access_clock: AtomicU64,
+}
+
+/// Directories and files used by a database that is loaded from disk.
+#[derive(Clone, Debug)]
+pub struct DatabasePaths {
+    /// Directory that chests are installed into. Chests in this directory are loaded and can
+    /// be uninstalled.
+    pub data_path: PathBuf,
+    /// Additional directories that chests are loaded from, such as a read-only system-wide
+    /// directory. Chests in these directories are never modified.
+    pub search_paths: Vec<PathBuf>,
+    /// Directory holding the public keys that are trusted to sign chests
+    pub trust_path: PathBuf,
+    /// Directory holding the cached contents of chests
+    pub cache_path: PathBuf,
+    /// File that is locked while chests are installed or uninstalled
+    pub lock_path: PathBuf,
+}
+
+/// Result of loading an entry in the data path, a search path or the trust store when
+/// loading the database.
+pub struct ChestDiagnostic {
+    pub path: PathBuf,
+    pub status: ChestLoadStatus,
+}
+
+/// Status of an entry in the data path or a search path after loading the database.
+pub enum ChestLoadStatus {
+    /// Chest was loaded with the given identifier
+    Loaded(String),
+    /// Chest has the same identifier as a chest that was already loaded, and was not loaded
+    Duplicate(String),
+    /// Chest could not be loaded, for example because it is corrupt or was created by a
+    /// newer version of docdelve
+    Failed(anyhow::Error),
+    /// Entry is not a chest
+    Ignored,
+    /// Entry is a trusted key file that could not be loaded. Chests signed by the key are
+    /// not trusted.
+    InvalidKey(anyhow::Error),
+    /// Chest was loaded with the given identifier, but claims a tag or alias that is also
+    /// claimed by chests with other tags. The name only refers to the chest if it exactly
+    /// matches the chest's tag.
+    TagCollision {
+        identifier: String,
+        name: String,
+        other_tags: Vec<String>,
+    },
 }
 
-/// A loaded chest with the files and semantic contents of the chest.
+/// Change to the chests in the database after reloading it.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum ChestChange {
+    /// Chest with the given identifier was added
+    Added(String),
+    /// Chest with the given identifier was removed
+    Removed(String),
+    /// Chest with the given identifier was replaced by a different chest on disk
+    Updated(String),
+}
+
+/// A loaded chest with the files and information of the chest. The semantic contents of the
+/// chest are loaded on first access.
 struct LoadedChest {
     chest: Chest,
-    contents: IndexedChestContents,
+    info: ChestInfo,
+    contents: OnceLock<IndexedChestContents>,
+    cache_key: Option<String>,
+    last_used: AtomicU64,
+    trust: ChestTrust,
+    /// Chest is in the data path and can be uninstalled
+    installed: bool,
+    /// Size and modification time of the chest when it was loaded
+    stamp: Option<FileStamp>,
+}
+
+/// Result of scanning the chest directories to reload the database.
+struct ChestScan {
+    /// Identifiers of the chests that were in the database when it was scanned
+    identifiers: BTreeSet<String>,
+    /// Entries in the chest directories, in the order they are loaded
+    chests: Vec<ScannedChest>,
+}
+
+/// Chest found when scanning the chest directories to reload the database.
+enum ScannedChest {
+    /// Entry that is not a chest
+    Ignored(PathBuf),
+    /// Chest that hasn't changed on disk since it was loaded into the database
+    Unchanged { identifier: String, path: PathBuf },
+    /// Chest that is new or has changed on disk, along with the result of opening it
+    Opened {
+        path: PathBuf,
+        loaded: Result<LoadedChest>,
+    },
 }
 
+/// Size and modification time of a chest on disk, used to detect chests that have changed
+/// when reloading the database. For chest directories, this is taken from the chest contents.
+#[derive(Clone, PartialEq, Eq, Debug)]
+struct FileStamp {
+    len: u64,
+    modified: Option<SystemTime>,
+}
+
+/// Environment variable that overrides the user's data directory
+pub const DATA_DIR_ENV: &'static str = "DOCDELVE_DATA_DIR";
+
+/// Environment variable holding additional directories to load chests from, separated in the
+/// same way as `PATH`
+pub const CHEST_PATH_ENV: &'static str = "DOCDELVE_CHEST_PATH";
+
 /// Database of all available versions of a specific chest identifier.
 #[derive(Default)]
 struct TagVersions {
-    latest_version: String,
-    versions: BTreeMap<String, String>,
+    latest_version: Version,
+    versions: BTreeMap<Version, String>,
 }
 
 /// Path to an item within all chests.
@@ -41,6 +167,30 @@ pub struct ItemPath {
 #[derive(Clone)]
 pub struct SearchParameters {
     pub result_count: usize,
+    /// Tag of the chest to search when no path is given, such as `Qt@6`. If the tag does not
+    /// match an installed chest, there are no results.
+    pub scope: Option<String>,
+}
+
+/// Parameters for installing a chest.
+#[derive(Clone, Default)]
+pub struct InstallParameters {
+    /// Verify the contents of the chest against its integrity manifest before installing it.
+    /// Chests that do not have a manifest or do not match it will not be installed.
+    pub verify: bool,
+    pub signature_policy: SignaturePolicy,
+}
+
+/// Handling of chest signatures when installing a chest.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum SignaturePolicy {
+    /// Install chests that are unsigned or not signed by a trusted key, but flag them as
+    /// such. The trust status is available through [Database::trust].
+    #[default]
+    Flag,
+    /// Only install chests that are signed by a trusted key. The contents of the chest are
+    /// always verified against the signed manifest.
+    Require,
 }
 
 /// A single search result.
@@ -63,90 +213,472 @@ pub struct ItemContents<'a> {
 }
 
 impl Database {
-    /// Loads the database and chests from disk.
+    /// Loads the database and chests from the user's data directory, see
+    /// [DatabasePaths::user].
     pub fn load() -> Result<Self> {
-        // Get the platform specific user directory where the chests are stored
-        let project_dirs = ProjectDirs::from("", "", "docdelve")
-            .ok_or_else(|| anyhow!("Invalid user directory"))?;
-        let data_path = project_dirs.data_local_dir().join("chests");
+        Self::load_from(DatabasePaths::user()?)
+    }
+
+    /// Loads the database and chests from the given directories. When more than one chest
+    /// has the same identifier, the chest in the data path is used, followed by the search
+    /// paths in order.
+    pub fn load_from(paths: DatabasePaths) -> Result<Self> {
+        let trusted_keys = TrustStore::load(&paths.trust_path)?;
+        let cache = ContentsCache::load(&paths.cache_path);
+        if paths.data_path.exists() {
+            Self::remove_stale_temporaries(&paths.data_path, &paths.lock_path);
+        }
+
+        let mut db = Self {
+            paths: Some(paths),
+            trusted_keys,
+            cache: Some(cache),
+            identifiers: BTreeMap::new(),
+            tags: BTreeMap::new(),
+            aliases: BTreeMap::new(),
+            diagnostics: Vec::new(),
+            max_loaded_chests: None,
+            access_clock: AtomicU64::new(0),
+        };
+        db.reload()?;
+
+        // Remove cached contents of chests that have been removed
+        if let Some(cache) = &db.cache {
+            cache.remove_unused(
+                &db.identifiers
+                    .keys()
+                    .map(|identifier| identifier.as_str())
+                    .collect(),
+            );
+        }
+
+        Ok(db)
+    }
+
+    /// Scans the data path and search paths again, and updates the database with chests that
+    /// were added, removed or replaced since the database was loaded. Chests that have not
+    /// changed on disk are kept as they are, including their loaded contents. Returns the
+    /// changes that were made to the database.
+    pub fn reload(&mut self) -> Result<Vec<ChestChange>> {
+        let scan = self.scan()?;
+        Ok(self.apply_scan(scan))
+    }
+
+    /// Reloads a shared database, like [Database::reload]. New and changed chests are opened
+    /// while only holding the read lock, the write lock is only held while the database is
+    /// updated with the result.
+    pub fn reload_shared(database: &RwLock<Database>) -> Result<Vec<ChestChange>> {
+        let scan = database
+            .read()
+            .map_err(|_| anyhow!("Database lock is poisoned"))?
+            .scan()?;
+        Ok(database
+            .write()
+            .map_err(|_| anyhow!("Database lock is poisoned"))?
+            .apply_scan(scan))
+    }
+
+    /// Scans the data path and search paths for chests. Chests that haven't changed on disk
+    /// since they were loaded are referenced by identifier, the rest are opened.
+    fn scan(&self) -> Result<ChestScan> {
+        let paths = self.required_paths()?;
+        let previous_paths = self
+            .identifiers
+            .values()
+            .filter_map(|loaded| Some((loaded.chest.path()?, loaded)))
+            .collect::<BTreeMap<_, _>>();
+
+        let mut scanned = Vec::new();
+        let chest_dirs = std::iter::once((&paths.data_path, true))
+            .chain(paths.search_paths.iter().map(|path| (path, false)));
+        for (chest_dir, installed) in chest_dirs {
+            if !chest_dir.exists() {
+                continue;
+            }
+
+            // Load entries in a consistent order, so that the same chest is loaded when there
+            // are duplicate identifiers
+            let mut dir_paths = chest_dir
+                .read_dir()?
+                .map(|entry| Ok(entry?.path()))
+                .collect::<Result<Vec<_>>>()?;
+            dir_paths.sort();
+
+            for path in dir_paths {
+                // Chests can be either chest files or unpacked chest directories
+                let is_chest = (path.is_file() && path.to_string_lossy().ends_with(".ddchest"))
+                    || (path.is_dir() && path.join(CONTENTS_PATH).is_file());
+                if !is_chest {
+                    scanned.push(ScannedChest::Ignored(path));
+                    continue;
+                }
+
+                // Keep chests that haven't changed since they were loaded, and open the rest
+                let stamp = FileStamp::read(&path);
+                let unchanged = previous_paths
+                    .get(path.as_path())
+                    .filter(|loaded| stamp.is_some() && loaded.stamp == stamp);
+                scanned.push(match unchanged {
+                    Some(loaded) => ScannedChest::Unchanged {
+                        identifier: loaded.info.identifier.clone(),
+                        path,
+                    },
+                    None => ScannedChest::Opened {
+                        loaded: self.open_chest(&path, installed, stamp),
+                        path,
+                    },
+                });
+            }
+        }
+        Ok(ChestScan {
+            identifiers: self.identifiers.keys().cloned().collect(),
+            chests: scanned,
+        })
+    }
+
+    /// Opens a chest in one of the chest directories for the database.
+    fn open_chest(
+        &self,
+        path: &Path,
+        installed: bool,
+        stamp: Option<FileStamp>,
+    ) -> Result<LoadedChest> {
+        Chest::open(path)
+            .and_then(|chest| LoadedChest::open(chest, self.cache.as_ref()))
+            .map(|loaded| LoadedChest {
+                trust: self.trusted_keys.check(&loaded.chest),
+                installed,
+                stamp,
+                ..loaded
+            })
+    }
+
+    /// Replaces the chests in the database with the result of a scan, and returns the changes
+    /// that were made. The result of loading each chest is kept, so that chests that can't be
+    /// loaded are reported instead of silently disappearing. Chests that were installed or
+    /// uninstalled since the scan keep their current state.
+    fn apply_scan(&mut self, scan: ChestScan) -> Vec<ChestChange> {
+        let mut previous = std::mem::take(&mut self.identifiers);
+        let previous_identifiers = previous.keys().cloned().collect::<BTreeSet<_>>();
 
-        // Load all chests into the database
         let mut identifiers: BTreeMap<String, LoadedChest> = BTreeMap::new();
         let mut tags: BTreeMap<String, TagVersions> = BTreeMap::new();
-        if data_path.exists() {
-            for entry in data_path.read_dir()? {
-                let entry = entry?;
-                if entry.file_type()?.is_file()
-                    && entry.file_name().to_string_lossy().ends_with(".ddchest")
-                {
-                    if let Ok(chest) = Chest::open(&data_path.join(entry.file_name())) {
-                        if let Ok(contents) = ChestContents::read_from_chest(&chest) {
-                            tags.entry(contents.info.category_tag.clone())
-                                .or_default()
-                                .versions
-                                .insert(
-                                    contents.info.version.clone(),
-                                    contents.info.identifier.clone(),
-                                );
-                            let identifier = contents.info.identifier.clone();
-                            identifiers.insert(
-                                identifier,
-                                LoadedChest {
-                                    chest,
-                                    contents: contents.to_indexed(),
-                                },
-                            );
-                        }
+        let mut diagnostics = self
+            .trusted_keys
+            .invalid_keys()
+            .iter()
+            .map(|(path, error)| ChestDiagnostic {
+                path: path.clone(),
+                status: ChestLoadStatus::InvalidKey(anyhow!(error.clone())),
+            })
+            .collect::<Vec<_>>();
+        let mut unchanged = BTreeSet::new();
+        for entry in scan.chests {
+            let (path, loaded, reused) = match entry {
+                ScannedChest::Ignored(path) => {
+                    diagnostics.push(ChestDiagnostic {
+                        path,
+                        status: ChestLoadStatus::Ignored,
+                    });
+                    continue;
+                }
+                ScannedChest::Unchanged { identifier, path } => {
+                    // The chest is missing if it was uninstalled since the scan
+                    match previous.remove(&identifier) {
+                        Some(loaded) => (path, Ok(loaded), true),
+                        None => continue,
                     }
                 }
+                ScannedChest::Opened { path, loaded } => (path, loaded, false),
+            };
+
+            let loaded = match loaded {
+                Ok(loaded) => loaded,
+                Err(error) => {
+                    diagnostics.push(ChestDiagnostic {
+                        path,
+                        status: ChestLoadStatus::Failed(error),
+                    });
+                    continue;
+                }
+            };
+            let identifier = loaded.info.identifier.clone();
+            if identifiers.contains_key(&identifier) {
+                diagnostics.push(ChestDiagnostic {
+                    path,
+                    status: ChestLoadStatus::Duplicate(identifier),
+                });
+                continue;
+            }
+            diagnostics.push(ChestDiagnostic {
+                path,
+                status: ChestLoadStatus::Loaded(identifier.clone()),
+            });
+            if reused {
+                unchanged.insert(identifier);
+            }
+            Self::insert_loaded(&mut identifiers, &mut tags, loaded);
+        }
+
+        // Keep chests that were installed since the scan, they are not changes of the reload
+        for (identifier, loaded) in previous {
+            if !scan.identifiers.contains(&identifier) && !identifiers.contains_key(&identifier) {
+                if let Some(path) = loaded.chest.path() {
+                    diagnostics.push(ChestDiagnostic {
+                        path: path.to_path_buf(),
+                        status: ChestLoadStatus::Loaded(identifier.clone()),
+                    });
+                }
+                unchanged.insert(identifier);
+                Self::insert_loaded(&mut identifiers, &mut tags, loaded);
             }
         }
 
         // For each chest identifier, detect the latest version
         for (_, identifier_versions) in tags.iter_mut() {
-            let mut versions = identifier_versions.versions.keys().collect::<Vec<_>>();
-            versions.sort_by_key(|version| Self::semantic_version(version));
-            if let Some(latest) = versions.last() {
-                identifier_versions.latest_version = (*latest).clone();
+            identifier_versions.update_latest_version();
+        }
+
+        // Compare against the chests that were loaded before to find the changes. Chests
+        // that are no longer present have their cached contents removed.
+        let mut changes = Vec::new();
+        for identifier in identifiers.keys() {
+            if !previous_identifiers.contains(identifier) {
+                changes.push(ChestChange::Added(identifier.clone()));
+            } else if !unchanged.contains(identifier) {
+                changes.push(ChestChange::Updated(identifier.clone()));
+            }
+        }
+        for identifier in previous_identifiers {
+            if !identifiers.contains_key(&identifier) {
+                if let Some(cache) = &self.cache {
+                    cache.remove(&identifier);
+                }
+                changes.push(ChestChange::Removed(identifier));
             }
         }
 
-        Ok(Self {
-            data_path,
+        self.identifiers = identifiers;
+        self.tags = tags;
+        self.diagnostics = diagnostics;
+        self.update_aliases();
+        changes
+    }
+
+    /// Creates a database that holds the given chests in memory, without a data directory.
+    /// Chests can't be installed into or uninstalled from the database, and their contents
+    /// are not cached. When more than one chest has the same identifier, the first is used.
+    pub fn from_chests(chests: Vec<Chest>) -> Result<Self> {
+        let trusted_keys = TrustStore::default();
+        let mut identifiers: BTreeMap<String, LoadedChest> = BTreeMap::new();
+        let mut tags: BTreeMap<String, TagVersions> = BTreeMap::new();
+        for chest in chests {
+            let loaded = LoadedChest::open(chest, None)?;
+            if identifiers.contains_key(&loaded.info.identifier) {
+                continue;
+            }
+            let trust = trusted_keys.check(&loaded.chest);
+            Self::insert_loaded(&mut identifiers, &mut tags, LoadedChest { trust, ..loaded });
+        }
+        for (_, identifier_versions) in tags.iter_mut() {
+            identifier_versions.update_latest_version();
+        }
+
+        let mut db = Self {
+            paths: None,
+            trusted_keys,
+            cache: None,
             identifiers,
             tags,
-        })
+            aliases: BTreeMap::new(),
+            diagnostics: Vec::new(),
+            max_loaded_chests: None,
+            access_clock: AtomicU64::new(0),
+        };
+        db.update_aliases();
+        Ok(db)
     }
 
-    /// Convert the version string into a semantic version that can be compared for
-    /// detecting the latest version.
-    fn semantic_version(version: &str) -> Vec<u32> {
-        let mut result = Vec::new();
-        version.split(&['.', '-', '_']).for_each(|part| {
-            if let Ok(num) = part.parse::<u32>() {
-                result.push(num);
-            }
-        });
-        result
+    /// Adds a loaded chest to the identifiers and tags of a database that is being loaded.
+    /// The latest version of the tag is not updated. If another chest already has the same
+    /// tag and version, that chest is kept as the version of the tag.
+    fn insert_loaded(
+        identifiers: &mut BTreeMap<String, LoadedChest>,
+        tags: &mut BTreeMap<String, TagVersions>,
+        loaded: LoadedChest,
+    ) {
+        let identifier = loaded.info.identifier.clone();
+        tags.entry(loaded.info.category_tag.clone())
+            .or_default()
+            .versions
+            .entry(Version::parse(&loaded.info.version))
+            .or_insert_with(|| identifier.clone());
+        identifiers.insert(identifier, loaded);
     }
 
     /// Installs a chest into the database.
-    pub fn install(&mut self, chest: &Chest) -> Result<()> {
+    pub fn install<F>(
+        &mut self,
+        chest: &Chest,
+        parameters: InstallParameters,
+        progress: F,
+    ) -> Result<()>
+    where
+        F: FnMut(ProgressEvent),
+    {
         // Load the chest contents, also ensures that it is a valid chest
-        let contents = ChestContents::read_from_chest(&chest)?;
+        let contents = IndexedChestContents::read_from_chest(chest)?;
 
-        // Copy the chest file into the data path
+        // Check the signature of the chest against the trusted keys
+        let trust = self.trusted_keys.check(chest);
+        if parameters.signature_policy == SignaturePolicy::Require {
+            match &trust {
+                ChestTrust::Trusted(_) => (),
+                ChestTrust::Untrusted => {
+                    return Err(anyhow!("Chest is not signed by a trusted key"))
+                }
+                ChestTrust::Unsigned => return Err(anyhow!("Chest is not signed")),
+                ChestTrust::Invalid => return Err(anyhow!("Chest signature is not valid")),
+            }
+        }
+
+        // Check the integrity of the chest if requested. The signature only covers the
+        // manifest, so the contents must also be verified when a signature is required, and
+        // before a chest is reported as trusted.
+        if parameters.verify
+            || parameters.signature_policy == SignaturePolicy::Require
+            || matches!(trust, ChestTrust::Trusted(_))
+        {
+            chest.verify(progress)?;
+        }
+
+        let data_path = self.required_paths()?.data_path.clone();
         let path = chest.path().ok_or_else(|| anyhow!("Chest has no path"))?;
-        let target_path = self.data_path.join(
-            path.file_name()
-                .ok_or_else(|| anyhow!("Chest path has no filename"))?,
-        );
-        std::fs::create_dir_all(&self.data_path)?;
-        std::fs::copy(&path, &target_path)?;
+        if path.is_dir() {
+            return Err(anyhow!(
+                "Chest directories must be packed into a chest file before installing"
+            ));
+        }
+        let file_name = path
+            .file_name()
+            .ok_or_else(|| anyhow!("Chest path has no filename"))?;
+
+        // An installed chest with the same identifier, or with the same tag and version, is
+        // replaced by the new chest
+        let replaced = self
+            .identifiers
+            .iter()
+            .filter(|(identifier, loaded)| {
+                **identifier == contents.info.identifier
+                    || (loaded.info.category_tag == contents.info.category_tag
+                        && loaded.info.version == contents.info.version)
+            })
+            .map(|(identifier, loaded)| {
+                let replaced_path = loaded.chest.path().map(|path| path.to_path_buf());
+                (identifier.clone(), replaced_path)
+            })
+            .collect::<Vec<_>>();
+        let is_replaced = |target: &Path| -> bool {
+            let target = target.canonicalize().ok();
+            target.is_some()
+                && replaced.iter().any(|(_, replaced_path)| {
+                    replaced_path
+                        .as_ref()
+                        .and_then(|path| path.canonicalize().ok())
+                        == target
+                })
+        };
+        if is_replaced(path) {
+            return Err(anyhow!("Chest is already installed"));
+        }
+
+        // Directory chests in the data path can't be deleted, so they can't be replaced
+        if let Some((identifier, _)) = replaced.iter().find(|(identifier, _)| {
+            let loaded = &self.identifiers[identifier];
+            loaded.installed && loaded.chest.is_dir()
+        }) {
+            return Err(anyhow!(
+                "Chest {} is a directory and can't be replaced",
+                identifier
+            ));
+        }
+
+        // Install under the original filename, unless there is already a file with that name
+        // that isn't being replaced
+        let mut target_path = data_path.join(file_name);
+        if target_path.exists() && !is_replaced(&target_path) {
+            target_path = data_path.join(format!(
+                "{}-{}.ddchest",
+                Path::new(file_name)
+                    .file_stem()
+                    .unwrap_or_default()
+                    .to_string_lossy(),
+                contents.info.identifier
+            ));
+        }
+
+        // Hold the install lock until the chest is in place, so that other processes don't
+        // modify the data path at the same time
+        std::fs::create_dir_all(&data_path)?;
+        let _lock = self.lock()?;
+
+        // Copy the chest file to a temporary file in the data path, and ensure that the copy
+        // is a valid chest before moving it into place. A copy that is interrupted is left as
+        // a temporary file, which is removed the next time the database is loaded.
+        let mut temp_file = tempfile::Builder::new()
+            .prefix(INSTALL_TEMP_PREFIX)
+            .suffix(INSTALL_TEMP_SUFFIX)
+            .tempfile_in(&data_path)?;
+        std::io::copy(&mut File::open(path)?, temp_file.as_file_mut())?;
+        temp_file.as_file().sync_all()?;
+        Self::check_installed_copy(&temp_file, &contents.info)?;
+
+        // Remove the replaced chests from the database. The chest at the target path is closed
+        // before the copy is moved over it, other replaced chests are deleted afterwards so
+        // that the chest is never missing. Chests outside of the data path are left in place,
+        // the installed chest takes priority over them when loading.
+        let target_canonical = target_path.canonicalize().ok();
+        let mut replaced_chests = Vec::new();
+        for (identifier, _) in &replaced {
+            let mut loaded = self.remove(identifier)?;
+            let replaced_canonical = loaded
+                .chest
+                .path()
+                .and_then(|path| path.canonicalize().ok());
+            let is_target = target_canonical.is_some() && replaced_canonical == target_canonical;
+            if is_target {
+                loaded.chest = Chest::new();
+            }
+            replaced_chests.push((loaded, is_target));
+        }
+        if let Err(error) = temp_file.persist(&target_path) {
+            // The replaced chests are still on disk, so put them back into the database
+            for (mut loaded, is_target) in replaced_chests {
+                if is_target {
+                    match Chest::open(&target_path) {
+                        Ok(chest) => loaded.chest = chest,
+                        Err(_) => continue,
+                    }
+                }
+                Self::insert_loaded(&mut self.identifiers, &mut self.tags, loaded);
+            }
+            for tag_versions in self.tags.values_mut() {
+                tag_versions.update_latest_version();
+            }
+            self.update_aliases();
+            return Err(error.into());
+        }
 
         // Reopen chest from new path. This frees up the original file so that it can be closed
         // and deleted if necessary.
         let chest = Chest::open(&target_path)?;
+        let stamp = FileStamp::read(&target_path);
+
+        // Cache the indexed contents so that they don't need to be indexed when loading
+        let cache_key = ContentsCache::key(&chest).ok();
+        if let Some((cache, key)) = self.cache.as_ref().zip(cache_key.as_ref()) {
+            let _ = cache.write(key, &contents);
+        }
 
         // Insert the chest into the database
         let tag_versions = self
@@ -154,49 +686,435 @@ impl Database {
             .entry(contents.info.category_tag.clone())
             .or_default();
         tag_versions.versions.insert(
-            contents.info.version.clone(),
+            Version::parse(&contents.info.version),
             contents.info.identifier.clone(),
         );
+        tag_versions.update_latest_version();
 
         let identifier = contents.info.identifier.clone();
         self.identifiers.insert(
             identifier,
             LoadedChest {
                 chest,
-                contents: contents.to_indexed(),
+                info: contents.info.clone(),
+                contents: OnceLock::from(contents),
+                cache_key,
+                last_used: AtomicU64::new(self.access_clock.fetch_add(1, AtomicOrdering::Relaxed)),
+                trust,
+                installed: true,
+                stamp,
             },
         );
+        self.update_aliases();
 
-        // Reevaluate latest version for this identifier
-        let mut versions = tag_versions.versions.values().collect::<Vec<_>>();
-        versions.sort_by_key(|version| Self::semantic_version(version));
-        if let Some(latest) = versions.last() {
-            tag_versions.latest_version = (*latest).clone();
+        // Delete the files of the other replaced chests now that the new chest is in place.
+        // The new chest stays installed if a file can't be deleted.
+        let mut delete_errors = Vec::new();
+        for (loaded, is_target) in replaced_chests {
+            if loaded.installed && !is_target {
+                let identifier = loaded.info.identifier.clone();
+                if let Err(error) = loaded.chest.delete() {
+                    delete_errors.push(format!("{}: {}", identifier, error));
+                }
+            }
+        }
+        if !delete_errors.is_empty() {
+            return Err(anyhow!(
+                "Chest was installed, but replaced chests could not be deleted ({})",
+                delete_errors.join(", ")
+            ));
         }
 
         Ok(())
     }
 
-    /// Gets a chest's contents by its identifier.
-    pub fn chest(&self, identifier: &str) -> Option<&IndexedChestContents> {
+    /// Uninstalls a chest from the database by its identifier. The chest is deleted from
+    /// the data path. Chests in the search paths and directory chests can't be uninstalled.
+    pub fn uninstall(&mut self, identifier: &str) -> Result<()> {
+        let loaded = self
+            .identifiers
+            .get(identifier)
+            .ok_or_else(|| anyhow!("Chest {} not found in database", identifier))?;
+        if !loaded.installed {
+            return Err(anyhow!(
+                "Chest {} is not in the data path and can't be uninstalled",
+                identifier
+            ));
+        }
+        if loaded.chest.is_dir() {
+            return Err(anyhow!(
+                "Chest {} is a directory and can't be uninstalled",
+                identifier
+            ));
+        }
+
+        std::fs::create_dir_all(&self.required_paths()?.data_path)?;
+        let _lock = self.lock()?;
+        self.remove(identifier)?.chest.delete()
+    }
+
+    /// Removes a chest from the database by its identifier, without deleting it from disk.
+    fn remove(&mut self, identifier: &str) -> Result<LoadedChest> {
+        let loaded = self
+            .identifiers
+            .remove(identifier)
+            .ok_or_else(|| anyhow!("Chest {} not found in database", identifier))?;
+
+        // Remove the chest from its tag and reevaluate the latest version of the tag
+        if let Some(tag_versions) = self.tags.get_mut(&loaded.info.category_tag) {
+            tag_versions
+                .versions
+                .retain(|_, version_identifier| version_identifier != identifier);
+            if tag_versions.versions.is_empty() {
+                self.tags.remove(&loaded.info.category_tag);
+            } else {
+                tag_versions.update_latest_version();
+            }
+        }
+
+        if let Some(cache) = &self.cache {
+            cache.remove(identifier);
+        }
+        self.update_aliases();
+        Ok(loaded)
+    }
+
+    /// Rebuilds the case-insensitive lookup of tags and tag aliases. A tag takes priority over
+    /// aliases of other tags that have the same name. Names that are claimed by more than one
+    /// tag otherwise are not used, and every chest that loses a name is reported in the
+    /// diagnostics.
+    fn update_aliases(&mut self) {
+        // Find the tags that claim each name, and whether they claim it as their own tag
+        let mut claims: BTreeMap<String, BTreeMap<&str, (bool, Vec<&LoadedChest>)>> =
+            BTreeMap::new();
+        for loaded in self.identifiers.values() {
+            let tag = loaded.info.category_tag.as_str();
+            let names = std::iter::once((tag, true)).chain(
+                loaded
+                    .info
+                    .category_tag_aliases
+                    .iter()
+                    .map(|alias| (alias.as_str(), false)),
+            );
+            for (name, is_tag) in names {
+                let claim = claims
+                    .entry(name.to_lowercase())
+                    .or_default()
+                    .entry(tag)
+                    .or_default();
+                claim.0 |= is_tag;
+                if !claim.1.iter().any(|chest| std::ptr::eq(*chest, loaded)) {
+                    claim.1.push(loaded);
+                }
+            }
+        }
+
+        let mut aliases = BTreeMap::new();
+        let mut collisions = Vec::new();
+        for (name, tags) in &claims {
+            let owners = if tags.values().any(|(is_tag, _)| *is_tag) {
+                tags.iter()
+                    .filter(|(_, (is_tag, _))| *is_tag)
+                    .map(|(tag, _)| *tag)
+                    .collect::<Vec<_>>()
+            } else {
+                tags.keys().copied().collect()
+            };
+            let owner = match owners.as_slice() {
+                [owner] => Some(*owner),
+                _ => None,
+            };
+            if let Some(owner) = owner {
+                aliases.insert(name.clone(), owner.to_string());
+            }
+            if tags.len() < 2 {
+                continue;
+            }
+
+            for (tag, (_, chests)) in tags {
+                if owner == Some(*tag) {
+                    continue;
+                }
+                let other_tags = tags
+                    .keys()
+                    .filter(|other| *other != tag)
+                    .map(|other| other.to_string())
+                    .collect::<Vec<_>>();
+                for chest in chests {
+                    collisions.push(ChestDiagnostic {
+                        path: chest
+                            .chest
+                            .path()
+                            .map(Path::to_path_buf)
+                            .unwrap_or_default(),
+                        status: ChestLoadStatus::TagCollision {
+                            identifier: chest.info.identifier.clone(),
+                            name: name.clone(),
+                            other_tags: other_tags.clone(),
+                        },
+                    });
+                }
+            }
+        }
+
+        self.aliases = aliases;
+        self.diagnostics.retain(|diagnostic| {
+            !matches!(diagnostic.status, ChestLoadStatus::TagCollision { .. })
+        });
+        self.diagnostics.extend(collisions);
+    }
+
+    /// Gets the paths of the database. Returns `None` if the database is only held in memory.
+    pub fn paths(&self) -> Option<&DatabasePaths> {
+        self.paths.as_ref()
+    }
+
+    /// Gets the paths of the database, or an error if the database is only held in memory.
+    fn required_paths(&self) -> Result<&DatabasePaths> {
+        self.paths
+            .as_ref()
+            .ok_or_else(|| anyhow!("Database does not have a data directory"))
+    }
+
+    /// Takes the exclusive lock on the data path, waiting for other processes that hold it.
+    /// The lock is released when the returned file is closed.
+    fn lock(&self) -> Result<File> {
+        let file = Self::open_lock_file(&self.required_paths()?.lock_path)?;
+        file.lock()?;
+        Ok(file)
+    }
+
+    /// Opens the file that is used to lock the data path.
+    fn open_lock_file(lock_path: &Path) -> Result<File> {
+        if let Some(parent) = lock_path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        Ok(OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(lock_path)?)
+    }
+
+    /// Removes temporary files left behind by installs that were interrupted. Temporary files
+    /// are only removed if no other process holds the lock, as the files could belong to an
+    /// install that is still running. Cleanup is best effort, the data path may be read only
+    /// or the lock may be unavailable, in which case the temporary files are left in place.
+    fn remove_stale_temporaries(data_path: &Path, lock_path: &Path) {
+        let lock = match Self::open_lock_file(lock_path) {
+            Ok(lock) => lock,
+            Err(_) => return,
+        };
+        if lock.try_lock().is_err() {
+            return;
+        }
+        let entries = match data_path.read_dir() {
+            Ok(entries) => entries,
+            Err(_) => return,
+        };
+
+        for entry in entries.flatten() {
+            let name = entry.file_name();
+            let name = name.to_string_lossy();
+            if name.starts_with(INSTALL_TEMP_PREFIX)
+                && name.ends_with(INSTALL_TEMP_SUFFIX)
+                && entry.file_type().is_ok_and(|file_type| file_type.is_file())
+            {
+                let _ = std::fs::remove_file(entry.path());
+            }
+        }
+    }
+
+    /// Checks that the copy of a chest that is being installed opens and contains the same
+    /// chest as the original.
+    fn check_installed_copy(temp_file: &NamedTempFile, info: &ChestInfo) -> Result<()> {
+        let copy = Chest::open(temp_file.path())
+            .map_err(|error| error.context("Copy of chest can't be opened"))?;
+        let copy_info = IndexedChestContents::read_from_chest(&copy)
+            .map_err(|error| error.context("Copy of chest can't be read"))?
+            .info;
+        if copy_info.identifier != info.identifier || copy_info.version != info.version {
+            return Err(anyhow!("Copy of chest does not match the original"));
+        }
+        Ok(())
+    }
+
+    /// Gets the result of loading each entry in the data path and search paths when the
+    /// database was loaded.
+    pub fn diagnostics(&self) -> &[ChestDiagnostic] {
+        &self.diagnostics
+    }
+
+    /// Adds a public key to the trusted keys for verifying chest signatures.
+    pub fn trust_key(&mut self, name: &str, public_key: &str) -> Result<()> {
+        let trust_path = self.required_paths()?.trust_path.clone();
+        self.trusted_keys.add(&trust_path, name, public_key)
+    }
+
+    /// Gets the trust status of a chest's signature by its identifier.
+    pub fn trust(&self, identifier: &str) -> Option<&ChestTrust> {
+        self.identifiers.get(identifier).map(|chest| &chest.trust)
+    }
+
+    /// Gets a chest's information by its identifier. This does not load the contents of
+    /// the chest.
+    pub fn chest_info(&self, identifier: &str) -> Option<&ChestInfo> {
+        self.identifiers.get(identifier).map(|chest| &chest.info)
+    }
+
+    /// Gets a chest's contents by its identifier. The contents are loaded if they are not
+    /// already loaded. Returns `None` if the chest doesn't exist, and an error if its contents
+    /// can't be loaded.
+    pub fn chest(&self, identifier: &str) -> Result<Option<&IndexedChestContents>> {
         self.identifiers
             .get(identifier)
-            .map(|chest| &chest.contents)
+            .map(|chest| self.contents(chest))
+            .transpose()
+    }
+
+    /// Loads the contents of the latest version of every chest using all available
+    /// threads, so that the first search doesn't have to wait for them to load.
+    pub fn warm_up(&self) {
+        self.latest_chests().par_iter().for_each(|(_, chest)| {
+            let _ = self.contents(chest);
+        });
+    }
+
+    /// Loads the contents of the latest version of every chest in a shared database, like
+    /// [Database::warm_up]. The lock is only held while each chest is loaded, so that the
+    /// database can be modified during the warm-up. Chests are evicted afterwards if there
+    /// are more loaded chests than the maximum.
+    pub fn warm_up_shared(database: &RwLock<Database>) {
+        let identifiers = match database.read() {
+            Ok(database) => database
+                .latest_chests()
+                .into_iter()
+                .map(|(identifier, _)| identifier.to_string())
+                .collect::<Vec<_>>(),
+            Err(_) => return,
+        };
+        identifiers.par_iter().for_each(|identifier| {
+            if let Ok(database) = database.read() {
+                let _ = database.chest(identifier);
+            }
+        });
+        if let Ok(mut database) = database.write() {
+            database.evict();
+        }
     }
 
-    /// Gets chest item(s) by path.
-    pub fn items_at_path(&self, path: &ItemPath) -> Vec<&IndexedChestItem> {
-        if let Some(chest) = self.identifiers.get(&path.identifier) {
-            return chest.contents.get(&path.chest_path);
+    /// Unloads the contents of a chest. The contents are loaded again on next access.
+    pub fn unload(&mut self, identifier: &str) {
+        if let Some(chest) = self.identifiers.get_mut(identifier) {
+            chest.contents.take();
+        }
+    }
+
+    /// Sets the maximum number of chests that have their contents loaded, or `None` for no
+    /// limit. Chests are only unloaded by [Database::evict], loading contents on access does
+    /// not enforce the limit.
+    pub fn set_max_loaded_chests(&mut self, max: Option<usize>) {
+        self.max_loaded_chests = max;
+    }
+
+    /// Checks whether more chests have their contents loaded than the maximum, so that
+    /// [Database::evict] would unload any of them.
+    pub fn needs_eviction(&self) -> bool {
+        match self.max_loaded_chests {
+            Some(max) => {
+                self.identifiers
+                    .values()
+                    .filter(|chest| chest.contents.get().is_some())
+                    .count()
+                    > max
+            }
+            None => false,
         }
-        Vec::new()
     }
 
-    /// Gets chest item contents by path.
-    pub fn item_contents_at_path(&self, path: &ItemPath) -> ItemContents {
-        if let Some(chest) = self.identifiers.get(&path.identifier) {
-            let items = chest.contents.get(&path.chest_path);
-            if items.len() == 1 {
+    /// Unloads the contents of the least recently used chests until no more than the
+    /// maximum number of loaded chests remain. Contents are loaded on access through a
+    /// shared reference, which can't unload other chests, so callers must call this after
+    /// accessing chests to keep memory in check.
+    pub fn evict(&mut self) {
+        let max = match self.max_loaded_chests {
+            Some(max) => max,
+            None => return,
+        };
+
+        let mut loaded = self
+            .identifiers
+            .iter_mut()
+            .filter(|(_, chest)| chest.contents.get().is_some())
+            .map(|(_, chest)| chest)
+            .collect::<Vec<_>>();
+        if loaded.len() <= max {
+            return;
+        }
+        loaded.sort_by_key(|chest| Reverse(chest.last_used.load(AtomicOrdering::Relaxed)));
+        for chest in loaded.into_iter().skip(max) {
+            chest.contents.take();
+        }
+    }
+
+    /// Gets the contents of a loaded chest, loading them if they are not already loaded.
+    fn contents<'a>(&self, chest: &'a LoadedChest) -> Result<&'a IndexedChestContents> {
+        chest.last_used.store(
+            self.access_clock.fetch_add(1, AtomicOrdering::Relaxed),
+            AtomicOrdering::Relaxed,
+        );
+        if let Some(contents) = chest.contents.get() {
+            return Ok(contents);
+        }
+
+        // Use the cached contents if they are up to date, otherwise read the contents from
+        // the chest and cache them for next time. Another thread may load the contents at
+        // the same time, in which case only one of the results is kept.
+        let cached = match self.cache.as_ref().zip(chest.cache_key.as_ref()) {
+            Some((cache, key)) => cache.read(&chest.info.identifier, key).unwrap_or(None),
+            None => None,
+        };
+        let contents = match cached {
+            Some(contents) => contents,
+            None => {
+                let contents = IndexedChestContents::read_from_chest(&chest.chest)?;
+                if let Some((cache, key)) = self.cache.as_ref().zip(chest.cache_key.as_ref()) {
+                    let _ = cache.write(key, &contents);
+                }
+                contents
+            }
+        };
+        Ok(chest.contents.get_or_init(|| contents))
+    }
+
+    /// Gets the latest version of every chest.
+    fn latest_chests(&self) -> Vec<(&str, &LoadedChest)> {
+        let mut result = Vec::new();
+        for versions in self.tags.values() {
+            if let Some(identifier) = versions.versions.get(&versions.latest_version) {
+                if let Some(chest) = self.identifiers.get(identifier) {
+                    result.push((identifier.as_str(), chest));
+                }
+            }
+        }
+        result
+    }
+
+    /// Gets chest item(s) by path. Returns an error if the contents of the chest can't be
+    /// loaded.
+    pub fn items_at_path(&self, path: &ItemPath) -> Result<Vec<&IndexedChestItem>> {
+        if let Some(contents) = self.chest(&path.identifier)? {
+            return Ok(contents.get(&path.chest_path));
+        }
+        Ok(Vec::new())
+    }
+
+    /// Gets chest item contents by path. Returns an error if the contents of the chest can't
+    /// be loaded.
+    pub fn item_contents_at_path(&self, path: &ItemPath) -> Result<ItemContents> {
+        if let Some(contents) = self.chest(&path.identifier)? {
+            let items = contents.get(&path.chest_path);
+            Ok(if items.len() == 1 {
                 // One item, return the contents directly
                 match &items[0].data {
                     IndexedChestItemData::Page(page) => ItemContents {
@@ -205,12 +1123,12 @@ impl Database {
                         bases: Vec::new(),
                     },
                     IndexedChestItemData::Object(object) => ItemContents {
-                        chest_items: items[0].contents(&chest.contents),
+                        chest_items: items[0].contents(contents),
                         page_items: Vec::new(),
                         bases: object.info.bases.clone(),
                     },
                     _ => ItemContents {
-                        chest_items: items[0].contents(&chest.contents),
+                        chest_items: items[0].contents(contents),
                         page_items: Vec::new(),
                         bases: Vec::new(),
                     },
@@ -227,10 +1145,10 @@ impl Database {
                             page_items.extend(page.info.contents.iter())
                         }
                         IndexedChestItemData::Object(object) => {
-                            chest_items.append(&mut item.contents(&chest.contents));
+                            chest_items.append(&mut item.contents(contents));
                             bases.extend(object.info.bases.iter().cloned());
                         }
-                        _ => chest_items.append(&mut item.contents(&chest.contents)),
+                        _ => chest_items.append(&mut item.contents(contents)),
                     }
                 }
                 ItemContents {
@@ -238,33 +1156,47 @@ impl Database {
                     page_items,
                     bases,
                 }
-            }
+            })
         } else {
-            ItemContents {
+            Ok(ItemContents {
                 chest_items: Vec::new(),
                 page_items: Vec::new(),
                 bases: Vec::new(),
-            }
+            })
         }
     }
 
     /// Searches all chests for items that match a string query. Search is performed within
     /// the given `path`, or all chests if `None`. The result is sorted by relevance, with the
     /// most relevant items first. Empty queries are not supported and return an empty result.
+    /// Returns an error if the contents of the chest being searched can't be loaded. When
+    /// searching all chests, chests whose contents can't be loaded are skipped.
     pub fn search(
         &self,
         path: Option<&ItemPath>,
         query: &str,
         parameters: SearchParameters,
-    ) -> Vec<SearchResult> {
+    ) -> Result<Vec<SearchResult>> {
+        // Search the root of the chest selected by the scope if there is no path
+        let scope_path = match (path, &parameters.scope) {
+            (None, Some(scope)) => match self.identifier_for_tag(scope) {
+                Some(identifier) => Some(ItemPath {
+                    identifier,
+                    chest_path: ChestPath::root(),
+                }),
+                None => return Ok(Vec::new()),
+            },
+            _ => None,
+        };
+        let path = path.or(scope_path.as_ref());
+
         let mut results = Vec::new();
         if let Some(path) = path {
             // Get the chest for the requested identifier
-            if let Some(chest) = self.identifiers.get(&path.identifier) {
+            if let Some(contents) = self.chest(&path.identifier)? {
                 // Search the requested chest
                 results.extend(
-                    chest
-                        .contents
+                    contents
                         .search(&path.chest_path, query, &parameters)
                         .into_iter()
                         .map(|result| SearchResult {
@@ -279,17 +1211,9 @@ impl Database {
         } else {
             // No path given, search latest version of all chests
             let mut all_contents = Vec::new();
-            for versions in self.tags.values() {
-                if let Some(identifier) = versions.versions.get(&versions.latest_version) {
-                    if let Some(chest) = self.identifiers.get(identifier) {
-                        all_contents.push((identifier.as_str(), &chest.contents));
-                        all_contents.push((identifier.as_str(), &chest.contents));
-                        all_contents.push((identifier.as_str(), &chest.contents));
-                        all_contents.push((identifier.as_str(), &chest.contents));
-                        all_contents.push((identifier.as_str(), &chest.contents));
-                        all_contents.push((identifier.as_str(), &chest.contents));
-                        all_contents.push((identifier.as_str(), &chest.contents));
-                    }
+            for (identifier, chest) in self.latest_chests() {
+                if let Ok(contents) = self.contents(chest) {
+                    all_contents.push((identifier, contents));
                 }
             }
 
@@ -315,26 +1239,26 @@ impl Database {
         results.sort_unstable_by(|a, b| a.cmp(&b));
         results.dedup();
         results.truncate(parameters.result_count);
-        results
+        Ok(results)
     }
 
     /// Gets the user visible tag name for a chest identifier. This will include the
     /// version number if the chest identifier references a version that isn't the latest.
     pub fn tag_for_identifier(&self, identifier: &str) -> Option<String> {
         if let Some(chest) = self.identifiers.get(identifier) {
-            if let Some(tag_versions) = self.tags.get(&chest.contents.info.category_tag) {
-                if tag_versions.latest_version == chest.contents.info.version {
-                    Some(chest.contents.info.category_tag.clone())
+            if let Some(tag_versions) = self.tags.get(&chest.info.category_tag) {
+                if tag_versions.latest_version.as_str() == chest.info.version {
+                    Some(chest.info.category_tag.clone())
                 } else {
                     Some(format!(
                         "{}@{}",
-                        chest.contents.info.category_tag, chest.contents.info.version
+                        chest.info.category_tag, chest.info.version
                     ))
                 }
             } else {
                 Some(format!(
                     "{}@{}",
-                    chest.contents.info.category_tag, chest.contents.info.version
+                    chest.info.category_tag, chest.info.version
                 ))
             }
         } else {
@@ -342,82 +1266,82 @@ impl Database {
         }
     }
 
-    /// Looks up the chest identifier for a given tag name.
+    /// Looks up the chest identifier for a given tag name or tag alias, ignoring case. The
+    /// tag can be followed by `@` and a version, which is matched exactly if there is a chest
+    /// with that version, and is otherwise used as a [VersionSelector], such as `Qt@6`,
+    /// `Qt@~6.5`, `Qt@>=6.2,<6.6` or `Rust@stable`. Without a version, the latest version of
+    /// the tag is used.
     pub fn identifier_for_tag(&self, tag: &str) -> Option<String> {
-        let parts = tag.split('@').collect::<Vec<_>>();
-        match parts.len() {
-            1 => {
-                // If no '@' is present, use latest version of the tag
-                if let Some(tag_versions) = self.tags.get(parts[0]) {
-                    tag_versions
-                        .versions
-                        .get(&tag_versions.latest_version)
-                        .map(|identifier| identifier.clone())
-                } else {
-                    None
-                }
-            }
-            2 => {
-                // If '@' is present, use the version specified
-                if let Some(tag_versions) = self.tags.get(parts[0]) {
-                    tag_versions
-                        .versions
-                        .get(parts[1])
-                        .map(|identifier| identifier.clone())
-                } else {
-                    None
-                }
-            }
-            _ => None,
-        }
+        let (tag, version) = match tag.split_once('@') {
+            Some((tag, version)) => (tag, Some(version)),
+            None => (tag, None),
+        };
+        let tag_versions = self.tag_versions(tag)?;
+        let version = match version {
+            Some(version) => tag_versions.select(version)?,
+            None => &tag_versions.latest_version,
+        };
+        tag_versions.versions.get(version).cloned()
     }
 
-    /// Gets the path corresponding to the item that a URL is pointing to.
+    /// Gets the versions of a tag. The tag is matched exactly first, and is otherwise looked
+    /// up case-insensitively among the tags and tag aliases.
+    fn tag_versions(&self, tag: &str) -> Option<&TagVersions> {
+        self.tags.get(tag).or_else(|| {
+            self.aliases
+                .get(&tag.to_lowercase())
+                .and_then(|tag| self.tags.get(tag))
+        })
+    }
+
+    /// Gets the path corresponding to the item that a URL is pointing to. Returns an error if
+    /// the contents of the chest can't be loaded.
     pub fn item_for_path(
         &self,
         identifier: &str,
         url: &str,
         path_hint: Option<&ItemPath>,
-    ) -> Option<ItemPath> {
-        if let Some(chest) = self.chest(identifier) {
+    ) -> Result<Option<ItemPath>> {
+        if let Some(chest) = self.chest(identifier)? {
             if let Some(path) = chest.item_for_path(url, path_hint.map(|path| &path.chest_path)) {
-                Some(ItemPath {
+                Ok(Some(ItemPath {
                     identifier: identifier.to_string(),
                     chest_path: path,
-                })
+                }))
             } else {
-                None
+                Ok(None)
             }
         } else {
-            None
+            Ok(None)
         }
     }
 
-    /// Gets the path corresponding to the page that an item is present on.
+    /// Gets the path corresponding to the page that an item is present on. Returns an error if
+    /// the contents of the chest can't be loaded.
     pub fn page_for_path(
         &self,
         identifier: &str,
         url: &str,
         path_hint: Option<&ItemPath>,
-    ) -> Option<ItemPath> {
-        if let Some(chest) = self.chest(identifier) {
+    ) -> Result<Option<ItemPath>> {
+        if let Some(chest) = self.chest(identifier)? {
             if let Some(path) = chest.page_for_path(url, path_hint.map(|path| &path.chest_path)) {
-                Some(ItemPath {
+                Ok(Some(ItemPath {
                     identifier: identifier.to_string(),
                     chest_path: path,
-                })
+                }))
             } else {
-                None
+                Ok(None)
             }
         } else {
-            None
+            Ok(None)
         }
     }
 
     /// Reads a file from a chest in the database.
     pub fn read(&self, identifier: &str, path: &str, theme: Theme) -> Result<Vec<u8>> {
         if let Some(chest) = self.identifiers.get(identifier) {
-            let path = chest.contents.transform_path_for_theme(path, theme);
+            let path = chest.info.transform_path_for_theme(path, theme);
             chest.chest.read(&path)
         } else {
             Err(anyhow!("Chest {} not found in database", identifier))
@@ -434,6 +1358,115 @@ impl Database {
     }
 }
 
+impl TagVersions {
+    /// Detects the latest version out of all versions of the tag. Stable releases are
+    /// preferred, a prerelease is only the latest version if there are no stable releases.
+    fn update_latest_version(&mut self) {
+        let latest = self
+            .versions
+            .keys()
+            .rev()
+            .find(|version| !version.is_prerelease())
+            .or_else(|| self.versions.keys().next_back());
+        if let Some(latest) = latest {
+            self.latest_version = latest.clone();
+        }
+    }
+
+    /// Selects a version of the tag by an exact version or a version selector.
+    fn select(&self, version: &str) -> Option<&Version> {
+        let exact = Version::parse(version);
+        if let Some((version, _)) = self.versions.get_key_value(&exact) {
+            return Some(version);
+        }
+        VersionSelector::parse(version)
+            .ok()?
+            .select(self.versions.keys())
+    }
+}
+
+impl DatabasePaths {
+    /// Gets the paths of a database with all of its files in a single directory. Chests are
+    /// installed into the `chests` subdirectory.
+    pub fn new(path: &Path) -> Self {
+        Self {
+            data_path: path.join("chests"),
+            search_paths: Vec::new(),
+            trust_path: path.join("trusted_keys"),
+            cache_path: path.join("cache"),
+            lock_path: path.join("install.lock"),
+        }
+    }
+
+    /// Gets the paths of the user's database. This is the platform specific user data
+    /// directory, unless it is overridden with the `DOCDELVE_DATA_DIR` environment variable.
+    /// Directories in the `DOCDELVE_CHEST_PATH` environment variable are added as search
+    /// paths.
+    pub fn user() -> Result<Self> {
+        let mut paths = match std::env::var_os(DATA_DIR_ENV) {
+            Some(path) if !path.is_empty() => Self::new(Path::new(&path)),
+            _ => {
+                // Get the platform specific user directory where the chests are stored
+                let project_dirs = ProjectDirs::from("", "", "docdelve")
+                    .ok_or_else(|| anyhow!("Invalid user directory"))?;
+                Self::new(project_dirs.data_local_dir())
+            }
+        };
+        if let Some(search_paths) = std::env::var_os(CHEST_PATH_ENV) {
+            paths.search_paths = std::env::split_paths(&search_paths)
+                .filter(|path| !path.as_os_str().is_empty())
+                .collect();
+        }
+        Ok(paths)
+    }
+}
+
+impl FileStamp {
+    /// Reads the size and modification time of a chest on disk. Returns `None` if the chest
+    /// can't be accessed.
+    fn read(path: &Path) -> Option<Self> {
+        let metadata = if path.is_dir() {
+            path.join(CONTENTS_PATH).metadata()
+        } else {
+            path.metadata()
+        }
+        .ok()?;
+        Some(Self {
+            len: metadata.len(),
+            modified: metadata.modified().ok(),
+        })
+    }
+}
+
+impl LoadedChest {
+    /// Opens a chest for the database. Only the chest information is read, from the cached
+    /// contents, the index of the chest or the chest contents in that order. The contents
+    /// themselves are read and cached the first time they are needed.
+    fn open(chest: Chest, cache: Option<&ContentsCache>) -> Result<Self> {
+        let cache_key = cache.and_then(|_| ContentsCache::key(&chest).ok());
+        let cached_info = cache
+            .zip(cache_key.as_deref())
+            .and_then(|(cache, key)| cache.info(key));
+        let info = match cached_info {
+            Some(info) => info.clone(),
+            None => match IndexedChestContents::read_info_from_chest(&chest) {
+                Ok(Some(info)) => info,
+                _ => ChestContents::read_info_from_chest(&chest)?,
+            },
+        };
+        Ok(Self {
+            chest,
+            info,
+            contents: OnceLock::new(),
+            cache_key,
+            last_used: AtomicU64::new(0),
+            trust: ChestTrust::Unsigned,
+            installed: false,
+            stamp: None,
+        })
+    }
+}
+
 impl SearchParameters {
     pub const DEFAULT_COUNT: usize = 20;
 }
@@ -442,6 +1475,7 @@ impl Default for SearchParameters {
     fn default() -> Self {
         Self {
             result_count: Self::DEFAULT_COUNT,
+            scope: None,
         }
     }
 }
@@ -478,3 +1512,170 @@ impl Ord for SearchResult {
         self.partial_cmp(other).unwrap()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::content::{
+        ChestItem, ChestPathElement, ChestPathElementType, Module, ModuleInfo, Object, ObjectInfo,
+        ObjectType,
+    };
+
+    /// Builds an in-memory chest with a module containing the given objects. Returns the chest
+    /// along with its identifier.
+    fn build_chest(tag: &str, version: &str, module: &str, objects: &[&str]) -> (Chest, String) {
+        let mut contents = ChestContents::new(tag, &[], None, version, "index.html", None, None);
+        contents.items.push(ChestItem::Module(Box::new(Module {
+            info: ModuleInfo {
+                name: module.to_string(),
+                full_name: module.to_string(),
+                url: None,
+            },
+            contents: objects
+                .iter()
+                .map(|name| {
+                    ChestItem::Object(Box::new(Object {
+                        info: ObjectInfo {
+                            name: name.to_string(),
+                            full_name: format!("{}::{}", module, name),
+                            declaration: None,
+                            url: Some(format!("{}.html", name.to_lowercase())),
+                            object_type: ObjectType::Class,
+                            bases: Vec::new(),
+                        },
+                        contents: Vec::new(),
+                    }))
+                })
+                .collect(),
+        })));
+
+        let mut chest = Chest::new();
+        contents.write_to_chest(&mut chest).unwrap();
+        (chest, contents.info.identifier)
+    }
+
+    /// Builds a path from the names of a module and optionally an object within it.
+    fn path(identifier: &str, module: &str, object: Option<&str>) -> ItemPath {
+        let mut elements = vec![ChestPathElement {
+            element_type: ChestPathElementType::Module,
+            name: module.to_string(),
+        }];
+        if let Some(object) = object {
+            elements.push(ChestPathElement {
+                element_type: ChestPathElementType::Object,
+                name: object.to_string(),
+            });
+        }
+        ItemPath {
+            identifier: identifier.to_string(),
+            chest_path: ChestPath { elements },
+        }
+    }
+
+    #[test]
+    fn from_chests() {
+        let (qt_chest, qt) = build_chest("Qt", "6.5.0", "QtCore", &["QString", "QVariant"]);
+        let (rust_chest, rust) = build_chest("Rust", "1.75.0", "std", &["Vec"]);
+        let db = Database::from_chests(vec![qt_chest, rust_chest]).unwrap();
+
+        assert_eq!(db.identifier_for_tag("Qt"), Some(qt.clone()));
+        assert_eq!(db.identifier_for_tag("Rust@1.75.0"), Some(rust.clone()));
+        assert_eq!(db.identifier_for_tag("Python"), None);
+
+        // Every chest is searched once, so each matching item is found exactly once
+        let results = db
+            .search(None, "QString", SearchParameters::default())
+            .unwrap();
+        let paths = results
+            .into_iter()
+            .map(|result| result.path)
+            .collect::<Vec<_>>();
+        assert_eq!(paths, vec![path(&qt, "QtCore", Some("QString"))]);
+
+        let results = db.search(None, "Vec", SearchParameters::default()).unwrap();
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].path, path(&rust, "std", Some("Vec")));
+
+        let items = db.items_at_path(&path(&qt, "QtCore", None)).unwrap();
+        assert_eq!(
+            items.iter().map(|item| item.name()).collect::<Vec<_>>(),
+            vec!["QtCore"]
+        );
+        let items = db.items_at_path(&path(&rust, "std", Some("Vec"))).unwrap();
+        assert_eq!(
+            items.iter().map(|item| item.name()).collect::<Vec<_>>(),
+            vec!["Vec"]
+        );
+        assert!(db
+            .items_at_path(&path(&rust, "QtCore", None))
+            .unwrap()
+            .is_empty());
+    }
+
+    /// Builds the versions of a tag, with each version as its own identifier.
+    fn tag_versions(versions: &[&str]) -> TagVersions {
+        let mut tag_versions = TagVersions::default();
+        for version in versions {
+            tag_versions
+                .versions
+                .insert(Version::parse(version), version.to_string());
+        }
+        tag_versions.update_latest_version();
+        tag_versions
+    }
+
+    #[test]
+    fn latest_version_prefers_stable() {
+        let tag = tag_versions(&["6.5.3", "6.6.0-beta1", "6.4.0"]);
+        assert_eq!(tag.latest_version.as_str(), "6.5.3");
+
+        let tag = tag_versions(&["6.5.3", "6.6.0-beta1", "6.6.0"]);
+        assert_eq!(tag.latest_version.as_str(), "6.6.0");
+
+        let tag = tag_versions(&["5.15.2", "5.15.2.1", "5.15.10"]);
+        assert_eq!(tag.latest_version.as_str(), "5.15.10");
+
+        // A prerelease is only the latest version when there are no stable releases
+        let tag = tag_versions(&["6.6.0-beta1", "6.6.0-beta10", "6.6.0-beta9"]);
+        assert_eq!(tag.latest_version.as_str(), "6.6.0-beta10");
+    }
+
+    #[test]
+    fn identifier_for_tag_with_selector() {
+        let versions = ["6", "6.2.0", "6.5.3", "6.6.0-beta1", "5.15.2.1"];
+        let (chests, identifiers): (Vec<_>, Vec<_>) = versions
+            .iter()
+            .map(|version| build_chest("Qt", version, "QtCore", &[]))
+            .unzip();
+        let db = Database::from_chests(chests).unwrap();
+        let identifier = |version: &str| {
+            let index = versions.iter().position(|v| *v == version).unwrap();
+            Some(identifiers[index].clone())
+        };
+
+        // Versions are matched exactly before they are used as a selector, so `Qt@6` selects
+        // the chest versioned `6` rather than the newest version starting with 6
+        assert_eq!(db.identifier_for_tag("Qt@6"), identifier("6"));
+        assert_eq!(db.identifier_for_tag("Qt@6.5"), identifier("6.5.3"));
+        assert_eq!(db.identifier_for_tag("Qt@5.15.2.1"), identifier("5.15.2.1"));
+        assert_eq!(db.identifier_for_tag("Qt@5"), identifier("5.15.2.1"));
+
+        assert_eq!(db.identifier_for_tag("Qt"), identifier("6.5.3"));
+        assert_eq!(db.identifier_for_tag("Qt@~6.2"), identifier("6.2.0"));
+        assert_eq!(db.identifier_for_tag("Qt@>=6.2,<6.6"), identifier("6.5.3"));
+        assert_eq!(
+            db.identifier_for_tag("Qt@>6.5.3"),
+            identifier("6.6.0-beta1")
+        );
+        assert_eq!(db.identifier_for_tag("Qt@stable"), identifier("6.5.3"));
+        assert_eq!(
+            db.identifier_for_tag("Qt@latest"),
+            identifier("6.6.0-beta1")
+        );
+        assert_eq!(db.identifier_for_tag("Qt@^7"), None);
+
+        for tag in ["Qt@", "Qt@abc", "Qt@>>6", "Qt@~", "Qt@6,", "Rust@6"] {
+            assert_eq!(db.identifier_for_tag(tag), None, "{} should not match", tag);
+        }
+    }
+}
@@ -0,0 +1,258 @@
+use crate::chest::{Chest, ChestManifest};
+use crate::content::CONTENTS_PATH;
+use crate::progress::ProgressEvent;
+use crate::signature::{ChestSignature, SIGNATURE_PATH};
+use anyhow::{anyhow, Error, Result};
+use diffy::Patch;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+use std::path::Path;
+
+/// Path of the delta information within a delta chest
+const DELTA_INFO_PATH: &'static str = "_chest_delta.json";
+
+/// Directory within a delta chest that holds added and modified files
+const DELTA_FILES_PATH: &'static str = "files";
+
+/// Set of changes that transforms one version of a chest into another. A delta is stored as
+/// a chest itself, containing the delta information and the contents of every file that was
+/// added or modified.
+pub struct ChestDelta {
+    info: ChestDeltaInfo,
+    chest: Chest,
+}
+
+/// Description of the changes within a chest delta.
+#[derive(Serialize, Deserialize)]
+pub struct ChestDeltaInfo {
+    pub source_version: String,
+    pub target_version: String,
+    /// Files that are present in the source but not in the target
+    pub removed: Vec<String>,
+    /// Files that were added or modified. The new contents are stored in the delta.
+    pub modified: Vec<String>,
+    /// Patch to apply to the chest contents, if the contents changed. The contents are split
+    /// into lines at the end of each item to keep the patch small.
+    pub contents_patch: Option<String>,
+    /// Manifest of the target chest, used to verify the result of applying the delta
+    pub target_manifest: ChestManifest,
+    /// Signature of the target chest, if the target chest is signed
+    pub target_signature: Option<ChestSignature>,
+}
+
+impl ChestDelta {
+    /// Computes the delta between a source chest and a target chest. The target chest must
+    /// have a manifest, so that the result of applying the delta can be verified.
+    pub fn create(source: &Chest, target: &Chest) -> Result<Self> {
+        let source_manifest = match ChestManifest::read_from_chest(source) {
+            Ok(manifest) => manifest,
+            Err(_) => ChestManifest::compute(source)?,
+        };
+        let target_manifest = ChestManifest::read_from_chest(target)?;
+        let source_contents = String::from_utf8(source.read(CONTENTS_PATH)?)?;
+        let target_contents = String::from_utf8(target.read(CONTENTS_PATH)?)?;
+
+        let mut chest = Chest::new();
+
+        // Files that are not in the target are removed
+        let removed = source_manifest
+            .files
+            .keys()
+            .filter(|path| !target_manifest.files.contains_key(*path))
+            .cloned()
+            .collect();
+
+        // Try to express changes to the chest contents as a patch. If the patch can't
+        // reproduce the target contents exactly, the contents are stored as a modified file.
+        let mut contents_patch = None;
+        let mut contents_modified = false;
+        if source_contents != target_contents {
+            match Self::contents_patch(&source_contents, &target_contents) {
+                Some(patch) => contents_patch = Some(patch),
+                None => contents_modified = true,
+            }
+        }
+
+        // Store the contents of all files that are new or have a different hash
+        let mut modified = Vec::new();
+        for (path, hash) in &target_manifest.files {
+            if path == CONTENTS_PATH && !contents_modified {
+                continue;
+            }
+            if source_manifest.files.get(path) != Some(hash) {
+                chest.write(
+                    &format!("{}/{}", DELTA_FILES_PATH, path),
+                    &target.read(path)?,
+                )?;
+                modified.push(path.clone());
+            }
+        }
+
+        let info = ChestDeltaInfo {
+            source_version: Self::version(&source_contents)?,
+            target_version: Self::version(&target_contents)?,
+            removed,
+            modified,
+            contents_patch,
+            target_manifest,
+            target_signature: ChestSignature::read_from_chest(target)?,
+        };
+        chest.write(DELTA_INFO_PATH, serde_json::to_string(&info)?.as_bytes())?;
+
+        Ok(Self { info, chest })
+    }
+
+    /// Opens a delta from disk.
+    pub fn open(path: &Path) -> Result<Self> {
+        let chest = Chest::open(path)?;
+        let info = serde_json::from_slice(&chest.read(DELTA_INFO_PATH)?)?;
+        Ok(Self { info, chest })
+    }
+
+    /// Saves the delta to disk.
+    pub fn save<F>(&mut self, path: &Path, progress: F) -> Result<()>
+    where
+        F: FnMut(ProgressEvent),
+    {
+        self.chest.save(path, progress)
+    }
+
+    /// Gets the description of the changes within the delta.
+    pub fn info(&self) -> &ChestDeltaInfo {
+        &self.info
+    }
+
+    /// Applies the delta to the source chest and saves the result to `path`. The source chest
+    /// is checked against the source of the delta before making any changes. The result is
+    /// saved to a temporary file next to `path`, and is only moved into place once it has been
+    /// verified against the manifest of the target chest.
+    pub fn apply<F>(&self, mut source: Chest, path: &Path, mut progress: F) -> Result<()>
+    where
+        F: FnMut(ProgressEvent),
+    {
+        // Check that the delta was created from this version of the chest. Files that the
+        // delta does not replace are carried over, so they must match the target manifest.
+        let source_contents = String::from_utf8(source.read(CONTENTS_PATH)?)?;
+        let source_version = Self::version(&source_contents)?;
+        if source_version != self.info.source_version {
+            return Err(anyhow!(
+                "Delta applies to version {}, but the chest is version {}",
+                self.info.source_version,
+                source_version
+            ));
+        }
+        for path in &self.info.removed {
+            if !source.contains(path) {
+                return Err(anyhow!(
+                    "Delta does not apply to this chest, '{}' not found",
+                    path
+                ));
+            }
+        }
+        let modified = self.info.modified.iter().collect::<BTreeSet<_>>();
+        for (path, hash) in &self.info.target_manifest.files {
+            let replaced = modified.contains(path)
+                || (path == CONTENTS_PATH && self.info.contents_patch.is_some());
+            if !replaced && (!source.contains(path) || source.hash(path)? != *hash) {
+                return Err(anyhow!(
+                    "Delta does not apply to this chest, '{}' does not match",
+                    path
+                ));
+            }
+        }
+
+        // Apply the changes to the chest contents first, as it needs the source contents
+        if let Some(patch) = &self.info.contents_patch {
+            let lines = Self::split_contents(&source_contents)
+                .ok_or_else(|| Error::msg("Chest contents can't be patched"))?;
+            let patch = Patch::from_str(patch)?;
+            let patched = diffy::apply(&lines, &patch)
+                .map_err(|_| Error::msg("Delta does not apply to this chest"))?;
+            source.write(CONTENTS_PATH, Self::join_contents(&patched).as_bytes())?;
+        }
+
+        // Remove files that are not in the target
+        for path in &self.info.removed {
+            source.remove(path)?;
+        }
+
+        // Write the contents of added and modified files
+        for path in &self.info.modified {
+            let contents = self.chest.read(&format!("{}/{}", DELTA_FILES_PATH, path))?;
+            source.write(path, &contents)?;
+        }
+
+        // Carry over the signature of the target. It is kept by the save only if it is
+        // valid for the resulting manifest.
+        if let Some(signature) = &self.info.target_signature {
+            source.write(SIGNATURE_PATH, serde_json::to_string(signature)?.as_bytes())?;
+        }
+
+        // Save to a temporary file in the same directory, so that a result that fails
+        // verification never replaces an existing chest at the target path
+        let dir = match path.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent,
+            _ => Path::new("."),
+        };
+        let temp_file = tempfile::Builder::new().suffix(".tmp").tempfile_in(dir)?;
+        source.save(temp_file.path(), &mut progress)?;
+        drop(source);
+
+        // Verify the result against the target manifest
+        let result = Chest::open(temp_file.path())?;
+        let manifest = ChestManifest::read_from_chest(&result)?;
+        if manifest.files != self.info.target_manifest.files {
+            return Err(Error::msg(
+                "Result of applying delta does not match the target manifest",
+            ));
+        }
+        result.verify(progress)?;
+        drop(result);
+
+        temp_file.persist(path)?;
+        Ok(())
+    }
+
+    /// Creates a patch for the chest contents. Returns `None` if a patch can't reproduce
+    /// the target contents exactly.
+    fn contents_patch(source: &str, target: &str) -> Option<String> {
+        let source_lines = Self::split_contents(source)?;
+        let target_lines = Self::split_contents(target)?;
+        let patch = diffy::create_patch(&source_lines, &target_lines).to_string();
+
+        // Ensure that the patch applies cleanly and yields the target
+        let parsed = Patch::from_str(&patch).ok()?;
+        let patched = diffy::apply(&source_lines, &parsed).ok()?;
+        if Self::join_contents(&patched) == target {
+            Some(patch)
+        } else {
+            None
+        }
+    }
+
+    /// Splits the chest contents into lines at the end of each item, so that the contents
+    /// can be patched line by line. Chest contents are written without line breaks, so
+    /// joining the lines back together restores the original. Returns `None` if the
+    /// contents already contain line breaks.
+    fn split_contents(contents: &str) -> Option<String> {
+        if contents.contains('\n') {
+            None
+        } else {
+            Some(contents.replace("},", "},\n"))
+        }
+    }
+
+    /// Joins chest contents that were split into lines with [ChestDelta::split_contents].
+    fn join_contents(lines: &str) -> String {
+        lines.replace('\n', "")
+    }
+
+    /// Gets the version of a chest from its contents without parsing all of the items.
+    fn version(contents: &str) -> Result<String> {
+        #[derive(Deserialize)]
+        struct Version {
+            version: String,
+        }
+        Ok(serde_json::from_str::<Version>(contents)?.version)
+    }
+}
@@ -1,5 +1,10 @@
+mod cache;
 pub mod chest;
 pub mod container;
 pub mod content;
 pub mod db;
+pub mod delta;
 pub mod progress;
+pub mod signature;
+pub mod version;
+pub mod watch;
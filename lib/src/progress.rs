@@ -9,6 +9,7 @@ pub enum ProgressEvent {
     Action(String),
     CompressChest(u64, u64),
     ExtractChest(u64, u64),
+    VerifyChest(u64, u64),
 }
 
 pub fn default_terminal_progress_event_handler(verbose: bool) -> Box<dyn Fn(ProgressEvent)> {
@@ -35,12 +36,20 @@ pub fn default_terminal_progress_event_handler(verbose: bool) -> Box<dyn Fn(Prog
                 print!("\r\x1b[2K{}...", desc)
             }
             ProgressEvent::CompressChest(done, total) => {
-                print!("\r\x1b[2KCompressing chest ({}%)...", (done * 100) / total)
+                print!("\r\x1b[2KCompressing chest ({}%)...", percent(done, total))
             }
             ProgressEvent::ExtractChest(done, total) => {
-                print!("\r\x1b[2KExtracting chest ({}%)...", (done * 100) / total)
+                print!("\r\x1b[2KExtracting chest ({}%)...", percent(done, total))
+            }
+            ProgressEvent::VerifyChest(done, total) => {
+                print!("\r\x1b[2KVerifying chest ({}%)...", percent(done, total))
             }
         }
         let _ = std::io::stdout().flush();
     })
 }
+
+/// Gets the percentage of completed work, treating empty work as complete.
+fn percent(done: u64, total: u64) -> u64 {
+    (done * 100).checked_div(total).unwrap_or(100)
+}
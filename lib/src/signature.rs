@@ -0,0 +1,227 @@
+use crate::chest::{Chest, MANIFEST_PATH};
+use anyhow::{anyhow, Error, Result};
+use ed25519_dalek::{Signature, Signer, SigningKey, VerifyingKey};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs::OpenOptions;
+use std::io::{ErrorKind, Write};
+#[cfg(unix)]
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+
+/// Path of the signature of the manifest within a chest
+pub const SIGNATURE_PATH: &'static str = "_chest_signature.json";
+
+/// Extension used for public key files in the trust store
+const PUBLIC_KEY_EXTENSION: &'static str = "pub";
+
+/// Private key used for signing chests.
+#[derive(Clone)]
+pub struct ChestSigningKey(SigningKey);
+
+/// Detached signature over the manifest of a chest, along with the public key of the signer.
+/// Keys and signatures are stored as hex strings.
+#[derive(Serialize, Deserialize)]
+pub struct ChestSignature {
+    pub public_key: String,
+    pub signature: String,
+}
+
+/// Set of public keys that are trusted to sign chests. On disk, each key is stored as a hex
+/// string in its own file within the trust store directory. The name of the key is the name
+/// of the file without the extension.
+#[derive(Default)]
+pub struct TrustStore {
+    keys: BTreeMap<String, VerifyingKey>,
+    /// Key files that could not be loaded, with the reason they were skipped
+    invalid_keys: Vec<(PathBuf, String)>,
+}
+
+/// Trust status of a chest based on its signature.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum ChestTrust {
+    /// Chest is signed by the named key in the trust store. The signature only covers the
+    /// manifest, the contents are verified against it when the chest is installed.
+    Trusted(String),
+    /// Chest has a valid signature, but the key is not in the trust store
+    Untrusted,
+    /// Chest does not have a signature
+    Unsigned,
+    /// Chest has a signature that does not match its manifest
+    Invalid,
+}
+
+impl ChestSigningKey {
+    /// Generate a new random signing key.
+    pub fn generate() -> Result<Self> {
+        let mut secret = [0; 32];
+        getrandom::getrandom(&mut secret)
+            .map_err(|error| anyhow!("Failed to generate key: {}", error))?;
+        Ok(Self(SigningKey::from_bytes(&secret)))
+    }
+
+    /// Read a signing key from a file.
+    pub fn from_file(path: &Path) -> Result<Self> {
+        let secret = hex::decode(std::fs::read_to_string(path)?.trim())?;
+        let secret = secret
+            .as_slice()
+            .try_into()
+            .map_err(|_| Error::msg("Signing key has an invalid length"))?;
+        Ok(Self(SigningKey::from_bytes(secret)))
+    }
+
+    /// Write the signing key to a new file. Fails if the file already exists, so that an
+    /// existing key is never lost. On Unix, the file is only readable by the owner.
+    pub fn write_to_file(&self, path: &Path) -> Result<()> {
+        let mut options = OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        options.mode(0o600);
+        let mut file = options.open(path).map_err(|error| {
+            if error.kind() == ErrorKind::AlreadyExists {
+                anyhow!("Key file '{}' already exists", path.display())
+            } else {
+                error.into()
+            }
+        })?;
+        file.write_all(hex::encode(self.0.to_bytes()).as_bytes())?;
+        Ok(file.sync_all()?)
+    }
+
+    /// Gets the public key for this signing key as a hex string.
+    pub fn public_key(&self) -> String {
+        hex::encode(self.0.verifying_key().to_bytes())
+    }
+
+    /// Signs the contents of a chest manifest.
+    pub fn sign(&self, manifest: &[u8]) -> ChestSignature {
+        ChestSignature {
+            public_key: self.public_key(),
+            signature: hex::encode(self.0.sign(manifest).to_bytes()),
+        }
+    }
+}
+
+impl ChestSignature {
+    /// Read the signature of a chest. Returns `None` if the chest is not signed.
+    pub fn read_from_chest(chest: &Chest) -> Result<Option<Self>> {
+        if !chest.contains(SIGNATURE_PATH) {
+            return Ok(None);
+        }
+        let contents = chest.read(SIGNATURE_PATH)?;
+        Ok(Some(serde_json::from_slice(&contents)?))
+    }
+
+    /// Checks the signature against the contents of a chest manifest. Returns the public key
+    /// of the signer if the signature is valid.
+    pub(crate) fn verify(&self, manifest: &[u8]) -> Result<VerifyingKey> {
+        let public_key = Self::public_key_from_hex(&self.public_key)?;
+        let signature = hex::decode(&self.signature)?;
+        let signature = Signature::from_slice(&signature)?;
+        public_key.verify_strict(manifest, &signature)?;
+        Ok(public_key)
+    }
+
+    /// Parses a public key from a hex string.
+    fn public_key_from_hex(public_key: &str) -> Result<VerifyingKey> {
+        let public_key = hex::decode(public_key.trim())?;
+        let public_key = public_key
+            .as_slice()
+            .try_into()
+            .map_err(|_| Error::msg("Public key has an invalid length"))?;
+        Ok(VerifyingKey::from_bytes(public_key)?)
+    }
+}
+
+impl TrustStore {
+    /// Loads the trust store from a directory. If the directory does not exist, the trust
+    /// store is empty. Key files that can't be read or don't hold a valid key are skipped,
+    /// and are available through [TrustStore::invalid_keys].
+    pub fn load(path: &Path) -> Result<Self> {
+        let mut keys = BTreeMap::new();
+        let mut invalid_keys = Vec::new();
+        if path.exists() {
+            for entry in path.read_dir()? {
+                let entry = entry?;
+                let path = entry.path();
+                if entry.file_type()?.is_file()
+                    && path.extension().map(|ext| ext == PUBLIC_KEY_EXTENSION) == Some(true)
+                {
+                    let name = path
+                        .file_stem()
+                        .ok_or_else(|| anyhow!("Trusted key path has no filename"))?
+                        .to_string_lossy()
+                        .to_string();
+                    let public_key = std::fs::read_to_string(&path)
+                        .map_err(Error::from)
+                        .and_then(|public_key| ChestSignature::public_key_from_hex(&public_key));
+                    match public_key {
+                        Ok(public_key) => {
+                            keys.insert(name, public_key);
+                        }
+                        Err(error) => invalid_keys.push((
+                            path,
+                            format!("Trusted key '{}' is invalid: {}", name, error),
+                        )),
+                    }
+                }
+            }
+        }
+        invalid_keys.sort();
+        Ok(Self { keys, invalid_keys })
+    }
+
+    /// Gets the key files that were skipped when loading the trust store, along with the
+    /// reason they could not be loaded.
+    pub fn invalid_keys(&self) -> &[(PathBuf, String)] {
+        &self.invalid_keys
+    }
+
+    /// Adds a public key to the trust store in the given directory and to this
+    /// loaded copy of the trust store. The name is used as the filename of the key, so it
+    /// can only contain ASCII letters, digits, '-' and '_'.
+    pub fn add(&mut self, path: &Path, name: &str, public_key: &str) -> Result<()> {
+        if name.is_empty()
+            || !name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+        {
+            return Err(anyhow!("Trusted key name '{}' is not valid", name));
+        }
+        let key = ChestSignature::public_key_from_hex(public_key)?;
+        let key_path = path.join(format!("{}.{}", name, PUBLIC_KEY_EXTENSION));
+        std::fs::create_dir_all(path)?;
+        std::fs::write(&key_path, hex::encode(key.to_bytes()))?;
+        self.keys.insert(name.to_string(), key);
+        self.invalid_keys
+            .retain(|(invalid_path, _)| *invalid_path != key_path);
+        Ok(())
+    }
+
+    /// Checks the signature of a chest against the trusted keys. Only the manifest is
+    /// covered by the signature, use [Chest::verify] to check that the contents of the
+    /// chest match the manifest.
+    pub fn check(&self, chest: &Chest) -> ChestTrust {
+        let signature = match ChestSignature::read_from_chest(chest) {
+            Ok(Some(signature)) => signature,
+            Ok(None) => return ChestTrust::Unsigned,
+            Err(_) => return ChestTrust::Invalid,
+        };
+        let manifest = match chest.read(MANIFEST_PATH) {
+            Ok(manifest) => manifest,
+            Err(_) => return ChestTrust::Invalid,
+        };
+
+        match signature.verify(&manifest) {
+            Ok(public_key) => {
+                for (name, key) in &self.keys {
+                    if key == &public_key {
+                        return ChestTrust::Trusted(name.clone());
+                    }
+                }
+                ChestTrust::Untrusted
+            }
+            Err(_) => ChestTrust::Invalid,
+        }
+    }
+}
@@ -0,0 +1,525 @@
+use anyhow::{anyhow, Result};
+use std::cmp::Ordering;
+use std::fmt::{Display, Formatter};
+
+/// Version of a chest. Versions are parsed in a semver-like form, with numeric release
+/// components followed by optional prerelease and build metadata, as in `6.6.0-beta1+build`.
+/// Any number of release components is accepted, so that four-part versions like `5.15.2.1`
+/// and short versions like `6.5` are compared correctly. Parsing never fails, parts of the
+/// version that don't fit the form are treated as prerelease identifiers.
+///
+/// Versions are ordered by precedence: release components are compared numerically with
+/// missing components treated as zero, and a prerelease comes before the release itself.
+/// Build metadata does not affect precedence. Versions with the same precedence are ordered
+/// by their original text, so that only identical versions are equal.
+#[derive(Clone, Debug, Default)]
+pub struct Version {
+    text: String,
+    release: Vec<u64>,
+    prerelease: Vec<PrereleaseIdentifier>,
+    build: Option<String>,
+}
+
+/// Selects a version out of the available versions of a chest, as in the `6` of `Qt@6`.
+/// Selectors can be one of:
+///
+/// * `latest` for the newest version, including prereleases
+/// * `stable` for the newest version that is not a prerelease
+/// * A partial version such as `6` or `6.5`, matching versions that start with it
+/// * A tilde range such as `~6.5`, matching patch releases of `6.5` at or above it
+/// * A caret range such as `^6.5`, matching releases compatible with `6.5` at or above it
+/// * Comparisons such as `>=6.2,<6.6`, where all comparisons must match
+///
+/// Missing components in versions of a selector are treated as zero. An upper bound excludes
+/// prereleases of the bound, so `~6.5` does not match `6.6.0-beta1`. When more than one
+/// version matches, the newest stable release is selected, or the newest prerelease if no
+/// stable release matches.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum VersionSelector {
+    Latest,
+    Stable,
+    Matches(Vec<VersionComparator>),
+}
+
+/// Single condition on a version within a [VersionSelector].
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum VersionComparator {
+    /// Version starts with the given release components, or has the same precedence if the
+    /// given version has a prerelease
+    Prefix(Version),
+    Greater(Version),
+    GreaterOrEqual(Version),
+    Less(Version),
+    LessOrEqual(Version),
+}
+
+/// Part of the prerelease of a version. Identifiers are split at dots, dashes and at
+/// boundaries between letters and digits, so that `beta10` comes after `beta9`.
+#[derive(Clone, PartialEq, Eq, Debug)]
+enum PrereleaseIdentifier {
+    Numeric(u64),
+    Alphanumeric(String),
+}
+
+impl Version {
+    /// Parses a version string. A leading `v` is ignored.
+    pub fn parse(text: &str) -> Self {
+        let trimmed = text.trim();
+        let (rest, build) = match trimmed.split_once('+') {
+            Some((rest, build)) => (rest, Some(build.to_string())),
+            None => (trimmed, None),
+        };
+        let rest = match rest.strip_prefix(['v', 'V']) {
+            Some(stripped) if stripped.starts_with(|c: char| c.is_ascii_digit()) => stripped,
+            _ => rest,
+        };
+
+        // Release components are the leading dot separated numbers, everything after them
+        // is the prerelease
+        let mut release = Vec::new();
+        let mut remaining = rest;
+        loop {
+            let digits = remaining
+                .find(|c: char| !c.is_ascii_digit())
+                .unwrap_or(remaining.len());
+            if digits == 0 {
+                break;
+            }
+            match remaining[..digits].parse::<u64>() {
+                Ok(component) => release.push(component),
+                Err(_) => break,
+            }
+            remaining = &remaining[digits..];
+            match remaining.strip_prefix('.') {
+                Some(next) if next.starts_with(|c: char| c.is_ascii_digit()) => remaining = next,
+                _ => break,
+            }
+        }
+
+        Self {
+            text: text.to_string(),
+            release,
+            prerelease: Self::parse_prerelease(remaining),
+            build,
+        }
+    }
+
+    /// Gets the original text of the version.
+    pub fn as_str(&self) -> &str {
+        &self.text
+    }
+
+    /// Gets the numeric release components of the version.
+    pub fn release(&self) -> &[u64] {
+        &self.release
+    }
+
+    /// Gets the build metadata of the version, if present.
+    pub fn build(&self) -> Option<&str> {
+        self.build.as_deref()
+    }
+
+    /// Checks if the version is a prerelease. Versions without any release components are
+    /// also treated as prereleases, as they can't be ordered against releases.
+    pub fn is_prerelease(&self) -> bool {
+        self.release.is_empty() || !self.prerelease.is_empty()
+    }
+
+    /// Compares the precedence of two versions, ignoring build metadata and differences in
+    /// how the version is written.
+    pub fn cmp_precedence(&self, other: &Self) -> Ordering {
+        // A version without a prerelease comes after all prereleases of the same release
+        self.cmp_release(other).then_with(|| {
+            match (self.prerelease.is_empty(), other.prerelease.is_empty()) {
+                (true, true) => Ordering::Equal,
+                (true, false) => Ordering::Greater,
+                (false, true) => Ordering::Less,
+                (false, false) => self.prerelease.cmp(&other.prerelease),
+            }
+        })
+    }
+
+    /// Compares the release components of two versions, ignoring the prerelease.
+    fn cmp_release(&self, other: &Self) -> Ordering {
+        let components = self.release.len().max(other.release.len());
+        for i in 0..components {
+            let a = self.release.get(i).copied().unwrap_or(0);
+            let b = other.release.get(i).copied().unwrap_or(0);
+            match a.cmp(&b) {
+                Ordering::Equal => (),
+                ordering => return ordering,
+            }
+        }
+        Ordering::Equal
+    }
+
+    /// Splits the prerelease part of a version into identifiers.
+    fn parse_prerelease(text: &str) -> Vec<PrereleaseIdentifier> {
+        let mut identifiers = Vec::new();
+        for part in text.split(['.', '-', '_']) {
+            let mut start = 0;
+            let chars = part.char_indices().collect::<Vec<_>>();
+            for i in 0..chars.len() {
+                let at_end = i + 1 == chars.len();
+                let boundary =
+                    !at_end && chars[i].1.is_ascii_digit() != chars[i + 1].1.is_ascii_digit();
+                if at_end || boundary {
+                    let end = if at_end { part.len() } else { chars[i + 1].0 };
+                    identifiers.push(PrereleaseIdentifier::parse(&part[start..end]));
+                    start = end;
+                }
+            }
+        }
+        identifiers
+    }
+}
+
+impl VersionSelector {
+    /// Parses a version selector.
+    pub fn parse(text: &str) -> Result<Self> {
+        let text = text.trim();
+        if text.eq_ignore_ascii_case("latest") {
+            return Ok(Self::Latest);
+        }
+        if text.eq_ignore_ascii_case("stable") {
+            return Ok(Self::Stable);
+        }
+
+        let mut comparators = Vec::new();
+        for part in text.split(',') {
+            comparators.extend(VersionComparator::parse(part.trim())?);
+        }
+        Ok(Self::Matches(comparators))
+    }
+
+    /// Checks if a version is selected by this selector, without considering other versions.
+    pub fn matches(&self, version: &Version) -> bool {
+        match self {
+            Self::Latest => true,
+            Self::Stable => !version.is_prerelease(),
+            Self::Matches(comparators) => comparators
+                .iter()
+                .all(|comparator| comparator.matches(version)),
+        }
+    }
+
+    /// Selects the best matching version out of a set of versions.
+    pub fn select<'a, I>(&self, versions: I) -> Option<&'a Version>
+    where
+        I: IntoIterator<Item = &'a Version>,
+    {
+        let matching = versions.into_iter().filter(|version| self.matches(version));
+        let (stable, prerelease) = matching.fold((None, None), |(stable, prerelease), version| {
+            if version.is_prerelease() {
+                (stable, Self::newest(prerelease, version))
+            } else {
+                (Self::newest(stable, version), prerelease)
+            }
+        });
+        match self {
+            Self::Latest => match (stable, prerelease) {
+                (Some(stable), Some(prerelease)) => Some(stable.max(prerelease)),
+                (stable, prerelease) => stable.or(prerelease),
+            },
+            _ => stable.or(prerelease),
+        }
+    }
+
+    fn newest<'a>(current: Option<&'a Version>, version: &'a Version) -> Option<&'a Version> {
+        match current {
+            Some(current) if current.cmp_precedence(version) != Ordering::Less => Some(current),
+            _ => Some(version),
+        }
+    }
+}
+
+impl VersionComparator {
+    /// Parses a single comparison of a version selector. Tilde and caret ranges are
+    /// expanded into a pair of comparisons.
+    fn parse(text: &str) -> Result<Vec<Self>> {
+        let (operator, version_text) = match text.find(|c: char| c.is_ascii_alphanumeric()) {
+            Some(start) => (text[..start].trim(), text[start..].trim()),
+            None => return Err(anyhow!("Invalid version selector '{}'", text)),
+        };
+        let version = Version::parse(version_text);
+        if version.release.is_empty() {
+            return Err(anyhow!("Invalid version selector '{}'", text));
+        }
+
+        Ok(match operator {
+            "" | "=" => vec![Self::Prefix(version)],
+            ">" => vec![Self::Greater(version)],
+            ">=" => vec![Self::GreaterOrEqual(version)],
+            "<" => vec![Self::Less(version)],
+            "<=" => vec![Self::LessOrEqual(version)],
+            "~" => {
+                // Allow changes after the minor version, or after the major version if only
+                // the major version is given
+                let fixed = version.release.len().clamp(1, 2);
+                let upper = Self::increment(&version.release[..fixed])
+                    .ok_or_else(|| anyhow!("Invalid version selector '{}'", text))?;
+                vec![Self::GreaterOrEqual(version), Self::Less(upper)]
+            }
+            "^" => {
+                // Allow changes after the first non-zero component
+                let fixed = version
+                    .release
+                    .iter()
+                    .position(|component| *component != 0)
+                    .map(|position| position + 1)
+                    .unwrap_or(version.release.len());
+                let upper = Self::increment(&version.release[..fixed])
+                    .ok_or_else(|| anyhow!("Invalid version selector '{}'", text))?;
+                vec![Self::GreaterOrEqual(version), Self::Less(upper)]
+            }
+            _ => return Err(anyhow!("Invalid version selector '{}'", text)),
+        })
+    }
+
+    /// Checks if a version matches this comparison.
+    fn matches(&self, version: &Version) -> bool {
+        match self {
+            Self::Prefix(prefix) if prefix.prerelease.is_empty() => {
+                prefix.release.iter().enumerate().all(|(i, component)| {
+                    version.release.get(i).copied().unwrap_or(0) == *component
+                })
+            }
+            Self::Prefix(prefix) => version.cmp_precedence(prefix) == Ordering::Equal,
+            Self::Greater(bound) => version.cmp_precedence(bound) == Ordering::Greater,
+            Self::GreaterOrEqual(bound) => version.cmp_precedence(bound) != Ordering::Less,
+            Self::Less(bound) if bound.prerelease.is_empty() => {
+                // Prereleases of the bound itself are excluded, as in semver, so that `<6.6`
+                // does not match `6.6.0-beta1`
+                version.cmp_release(bound) == Ordering::Less
+            }
+            Self::Less(bound) => version.cmp_precedence(bound) == Ordering::Less,
+            Self::LessOrEqual(bound) => version.cmp_precedence(bound) != Ordering::Greater,
+        }
+    }
+
+    /// Gets the version that follows all versions starting with the given components.
+    /// Returns `None` if the last component can't be incremented.
+    fn increment(components: &[u64]) -> Option<Version> {
+        let mut components = components.to_vec();
+        if let Some(last) = components.last_mut() {
+            *last = last.checked_add(1)?;
+        }
+        Some(Version::parse(
+            &components
+                .iter()
+                .map(|component| component.to_string())
+                .collect::<Vec<_>>()
+                .join("."),
+        ))
+    }
+}
+
+impl PrereleaseIdentifier {
+    fn parse(text: &str) -> Self {
+        match text.parse::<u64>() {
+            Ok(number) => Self::Numeric(number),
+            Err(_) => Self::Alphanumeric(text.to_ascii_lowercase()),
+        }
+    }
+}
+
+impl PartialEq for Version {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Version {}
+
+impl PartialOrd for Version {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Version {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.cmp_precedence(other)
+            .then_with(|| self.text.cmp(&other.text))
+    }
+}
+
+impl PartialOrd for PrereleaseIdentifier {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for PrereleaseIdentifier {
+    fn cmp(&self, other: &Self) -> Ordering {
+        // Numeric identifiers come before alphanumeric identifiers, as in semver
+        match (self, other) {
+            (Self::Numeric(a), Self::Numeric(b)) => a.cmp(b),
+            (Self::Numeric(_), Self::Alphanumeric(_)) => Ordering::Less,
+            (Self::Alphanumeric(_), Self::Numeric(_)) => Ordering::Greater,
+            (Self::Alphanumeric(a), Self::Alphanumeric(b)) => a.cmp(b),
+        }
+    }
+}
+
+impl From<&str> for Version {
+    fn from(text: &str) -> Self {
+        Self::parse(text)
+    }
+}
+
+impl Display for Version {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.text)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn precedence(a: &str, b: &str) -> Ordering {
+        Version::parse(a).cmp_precedence(&Version::parse(b))
+    }
+
+    #[test]
+    fn prerelease_before_release() {
+        assert_eq!(precedence("6.6.0-beta1", "6.6.0"), Ordering::Less);
+        assert_eq!(precedence("6.6.0-rc1", "6.5.3"), Ordering::Greater);
+        assert_eq!(precedence("6.6.0-beta1", "6.6.0-rc1"), Ordering::Less);
+        assert!(Version::parse("6.6.0-beta1").is_prerelease());
+        assert!(!Version::parse("6.6.0").is_prerelease());
+    }
+
+    #[test]
+    fn numeric_prerelease_parts() {
+        assert_eq!(precedence("6.6.0-beta9", "6.6.0-beta10"), Ordering::Less);
+        assert_eq!(precedence("6.6.0-beta.9", "6.6.0-beta.10"), Ordering::Less);
+        assert_eq!(precedence("6.6.0-Beta2", "6.6.0-beta2"), Ordering::Equal);
+    }
+
+    #[test]
+    fn four_part_versions() {
+        assert_eq!(Version::parse("5.15.2.1").release(), &[5, 15, 2, 1]);
+        assert_eq!(precedence("5.15.2.1", "5.15.2"), Ordering::Greater);
+        assert_eq!(precedence("5.15.2.1", "5.15.3"), Ordering::Less);
+        assert_eq!(precedence("5.15.2.0", "5.15.2"), Ordering::Equal);
+        assert_eq!(precedence("6.5", "6.5.0"), Ordering::Equal);
+        assert_eq!(precedence("5.15.10", "5.15.9"), Ordering::Greater);
+    }
+
+    #[test]
+    fn v_prefix() {
+        let version = Version::parse("v6.5.0");
+        assert_eq!(version.release(), &[6, 5, 0]);
+        assert!(!version.is_prerelease());
+        assert_eq!(version.as_str(), "v6.5.0");
+        assert_eq!(precedence("v6.5.0", "6.5.0"), Ordering::Equal);
+        assert_eq!(precedence("V1.2-beta1", "1.2"), Ordering::Less);
+    }
+
+    #[test]
+    fn build_metadata_ignored() {
+        let version = Version::parse("6.5.0+build.42");
+        assert_eq!(version.build(), Some("build.42"));
+        assert!(!version.is_prerelease());
+        assert_eq!(precedence("6.5.0+build.42", "6.5.0"), Ordering::Equal);
+        assert_eq!(precedence("6.5.0+b1", "6.5.0+b2"), Ordering::Equal);
+        assert_eq!(precedence("6.5.0+b9", "6.5.1"), Ordering::Less);
+
+        // Versions with the same precedence are only equal if they are written the same
+        assert_ne!(Version::parse("6.5.0+b1"), Version::parse("6.5.0+b2"));
+        assert_eq!(Version::parse("6.5.0+b1"), Version::parse("6.5.0+b1"));
+    }
+
+    /// Selects a version out of the given versions, returning the text of the selected version.
+    fn select(selector: &str, versions: &[&str]) -> Option<String> {
+        let versions = versions
+            .iter()
+            .map(|version| Version::parse(version))
+            .collect::<Vec<_>>();
+        VersionSelector::parse(selector)
+            .unwrap()
+            .select(&versions)
+            .map(|version| version.as_str().to_string())
+    }
+
+    const QT_VERSIONS: &[&str] = &["5.15.2", "6.2.0", "6.5.0", "6.5.3", "6.6.0-beta1"];
+
+    #[test]
+    fn partial_version_selector() {
+        assert_eq!(select("6", QT_VERSIONS).as_deref(), Some("6.5.3"));
+        assert_eq!(select("6.2", QT_VERSIONS).as_deref(), Some("6.2.0"));
+        assert_eq!(select("5", QT_VERSIONS).as_deref(), Some("5.15.2"));
+        assert_eq!(select("7", QT_VERSIONS), None);
+
+        // Prereleases are only selected when no stable release matches
+        assert_eq!(select("6.6", QT_VERSIONS).as_deref(), Some("6.6.0-beta1"));
+    }
+
+    #[test]
+    fn tilde_selector() {
+        assert_eq!(select("~6.5", QT_VERSIONS).as_deref(), Some("6.5.3"));
+        assert_eq!(select("~6.5.1", QT_VERSIONS).as_deref(), Some("6.5.3"));
+        assert_eq!(select("~6.4", QT_VERSIONS), None);
+        assert_eq!(
+            VersionSelector::parse("~6.5").unwrap(),
+            VersionSelector::Matches(vec![
+                VersionComparator::GreaterOrEqual(Version::parse("6.5")),
+                VersionComparator::Less(Version::parse("6.6")),
+            ])
+        );
+    }
+
+    #[test]
+    fn caret_selector() {
+        let versions = &["0.2.9", "0.3.0", "0.3.5", "0.4.0", "1.0.0"];
+        assert_eq!(select("^0.3", versions).as_deref(), Some("0.3.5"));
+        assert_eq!(select("^0.3.1", versions).as_deref(), Some("0.3.5"));
+        assert_eq!(select("^0.4", versions).as_deref(), Some("0.4.0"));
+        assert_eq!(select("^1", versions).as_deref(), Some("1.0.0"));
+        assert_eq!(select("^6.2", QT_VERSIONS).as_deref(), Some("6.5.3"));
+    }
+
+    #[test]
+    fn comparison_selector() {
+        let selector = VersionSelector::parse(">=6.2,<6.6").unwrap();
+        assert!(selector.matches(&Version::parse("6.2.0")));
+        assert!(selector.matches(&Version::parse("6.5.3")));
+        assert!(!selector.matches(&Version::parse("6.1.9")));
+        assert!(!selector.matches(&Version::parse("6.6.0")));
+        assert!(!selector.matches(&Version::parse("6.6.0-beta1")));
+        assert_eq!(select(">=6.2,<6.6", QT_VERSIONS).as_deref(), Some("6.5.3"));
+        assert_eq!(select(">=6.2, <6.5", QT_VERSIONS).as_deref(), Some("6.2.0"));
+        assert_eq!(
+            select(">6.5.3", QT_VERSIONS).as_deref(),
+            Some("6.6.0-beta1")
+        );
+        assert_eq!(select("<=5.15.2", QT_VERSIONS).as_deref(), Some("5.15.2"));
+    }
+
+    #[test]
+    fn stable_and_latest_selectors() {
+        assert_eq!(select("stable", QT_VERSIONS).as_deref(), Some("6.5.3"));
+        assert_eq!(
+            select("latest", QT_VERSIONS).as_deref(),
+            Some("6.6.0-beta1")
+        );
+        assert_eq!(select("Stable", QT_VERSIONS).as_deref(), Some("6.5.3"));
+
+        let versions = &["6.6.0-beta1", "6.6.0-rc1"];
+        assert_eq!(select("stable", versions), None);
+        assert_eq!(select("latest", versions).as_deref(), Some("6.6.0-rc1"));
+    }
+
+    #[test]
+    fn invalid_selectors() {
+        for selector in ["", "abc", ">>6", "~", "6,", "=>6", "~18446744073709551615"] {
+            assert!(
+                VersionSelector::parse(selector).is_err(),
+                "{} should be invalid",
+                selector
+            );
+        }
+    }
+}
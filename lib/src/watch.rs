@@ -0,0 +1,102 @@
+use crate::db::{ChestChange, Database, INSTALL_TEMP_PREFIX, INSTALL_TEMP_SUFFIX};
+use anyhow::{anyhow, Result};
+use notify::event::{AccessKind, AccessMode};
+use notify::{Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+/// Time without further changes to wait before reloading the database, so that a chest that
+/// is still being copied into place is only loaded once it is complete
+const SETTLE_TIME: Duration = Duration::from_millis(500);
+
+/// Watches the data path and search paths of a database, and reloads the database when
+/// chests are added, removed or replaced. Only the entries directly within the chest
+/// directories are watched, changes within unpacked chest directories are not picked up.
+/// Watching stops when the watcher is dropped.
+pub struct DatabaseWatcher {
+    _watcher: RecommendedWatcher,
+}
+
+impl DatabaseWatcher {
+    /// Starts watching the chest directories of a database. The callback is called from a
+    /// background thread with the changes after each reload that changed the database.
+    pub fn new<F>(database: Arc<RwLock<Database>>, mut callback: F) -> Result<Self>
+    where
+        F: FnMut(Vec<ChestChange>) + Send + 'static,
+    {
+        let paths = database
+            .read()
+            .map_err(|_| anyhow!("Database lock is poisoned"))?
+            .paths()
+            .ok_or_else(|| anyhow!("Database does not have a data directory"))?
+            .clone();
+
+        // Forward relevant events to the reload thread
+        let (sender, receiver) = mpsc::channel();
+        let mut watcher = notify::recommended_watcher(move |event: notify::Result<Event>| {
+            if let Ok(event) = event {
+                if Self::is_chest_change(&event) {
+                    let _ = sender.send(());
+                }
+            }
+        })?;
+
+        // The data path is created so that chests added later are picked up. Search paths
+        // that don't exist are not watched.
+        std::fs::create_dir_all(&paths.data_path)?;
+        watcher.watch(&paths.data_path, RecursiveMode::NonRecursive)?;
+        for path in &paths.search_paths {
+            if path.is_dir() {
+                watcher.watch(path, RecursiveMode::NonRecursive)?;
+            }
+        }
+
+        // Reload the database once changes have settled. The thread exits when the watcher
+        // is dropped, as that closes the channel.
+        std::thread::spawn(move || {
+            while receiver.recv().is_ok() {
+                loop {
+                    match receiver.recv_timeout(SETTLE_TIME) {
+                        Ok(()) => continue,
+                        Err(RecvTimeoutError::Timeout) => break,
+                        Err(RecvTimeoutError::Disconnected) => return,
+                    }
+                }
+
+                // Chests are opened without blocking readers of the database, which is only
+                // locked for writing while the reloaded chests are swapped in
+                if database.is_poisoned() {
+                    return;
+                }
+                if let Ok(changes) = Database::reload_shared(&database) {
+                    if !changes.is_empty() {
+                        callback(changes);
+                    }
+                }
+            }
+        });
+
+        Ok(Self { _watcher: watcher })
+    }
+
+    /// Checks if a file system event could change the chests in the database. Reads of
+    /// chests and changes to temporary files of installs in progress are ignored. Events
+    /// without paths are kept, as they signal that events may have been missed.
+    fn is_chest_change(event: &Event) -> bool {
+        let kind_matches = match event.kind {
+            EventKind::Access(AccessKind::Close(AccessMode::Write)) => true,
+            EventKind::Access(_) => false,
+            _ => true,
+        };
+        kind_matches
+            && (event.paths.is_empty()
+                || event.paths.iter().any(|path| {
+                    let name = path
+                        .file_name()
+                        .map(|name| name.to_string_lossy())
+                        .unwrap_or_default();
+                    !(name.starts_with(INSTALL_TEMP_PREFIX) && name.ends_with(INSTALL_TEMP_SUFFIX))
+                }))
+    }
+}
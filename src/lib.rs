@@ -1,7 +1,9 @@
-use napi::bindgen_prelude::{Buffer, JsError, Status};
+use napi::bindgen_prelude::{AsyncTask, Buffer, JsError, Status};
+use napi::threadsafe_function::{ErrorStrategy, ThreadsafeFunction, ThreadsafeFunctionCallMode};
+use napi::{Env, JsFunction, Task};
 use napi_derive::napi;
 use std::collections::BTreeSet;
-use std::sync::RwLock;
+use std::sync::{Arc, RwLock};
 
 // Bridge error type for auto-converting anyhow::Error into napi::Error and JsError
 pub struct Error(napi::Error);
@@ -9,7 +11,14 @@ pub struct Error(napi::Error);
 pub type Result<T> = std::result::Result<T, Error>;
 
 #[napi]
-pub struct Database(RwLock<docdelve::db::Database>);
+pub struct Database(Arc<RwLock<docdelve::db::Database>>);
+
+// Background task for loading the contents of all chests
+pub struct WarmUp(Arc<RwLock<docdelve::db::Database>>);
+
+// Watches the chest directories of a database until it is stopped or garbage collected
+#[napi]
+pub struct DatabaseWatcher(Option<docdelve::watch::DatabaseWatcher>);
 
 #[napi(object)]
 pub struct ChestContents {
@@ -110,6 +119,13 @@ pub struct SearchResult {
 #[napi(object)]
 pub struct SearchParameters {
     pub result_count: u32,
+    pub scope: Option<String>,
+}
+
+#[napi(object)]
+pub struct InstallParameters {
+    pub verify: bool,
+    pub require_signature: bool,
 }
 
 #[napi(object)]
@@ -141,6 +157,45 @@ pub enum Theme {
     Dark,
 }
 
+#[napi(string_enum)]
+pub enum ChestTrust {
+    Trusted,
+    Untrusted,
+    Unsigned,
+    Invalid,
+}
+
+#[napi(object)]
+pub struct ChestDiagnostic {
+    pub path: String,
+    pub status: ChestLoadStatus,
+    pub identifier: Option<String>,
+    pub error: Option<String>,
+}
+
+#[napi(string_enum)]
+pub enum ChestLoadStatus {
+    Loaded,
+    Duplicate,
+    Failed,
+    Ignored,
+    InvalidKey,
+    TagCollision,
+}
+
+#[napi(object)]
+pub struct ChestChange {
+    pub change_type: ChestChangeType,
+    pub identifier: String,
+}
+
+#[napi(string_enum)]
+pub enum ChestChangeType {
+    Added,
+    Removed,
+    Updated,
+}
+
 #[napi(object)]
 pub struct ItemContents {
     pub chest_items: Vec<ChestItem>,
@@ -152,43 +207,141 @@ pub struct ItemContents {
 impl Database {
     #[napi(constructor)]
     pub fn load() -> Result<Self> {
-        Ok(Self(RwLock::new(docdelve::db::Database::load()?)))
+        Ok(Self(Arc::new(RwLock::new(docdelve::db::Database::load()?))))
     }
 
     #[napi]
-    pub fn chest(&self, identifier: String) -> Option<ChestContents> {
+    pub fn warm_up(&self) -> AsyncTask<WarmUp> {
+        AsyncTask::new(WarmUp(self.0.clone()))
+    }
+
+    #[napi]
+    pub fn unload(&self, identifier: String) {
+        self.0.write().unwrap().unload(&identifier)
+    }
+
+    #[napi]
+    pub fn set_max_loaded_chests(&self, max: Option<u32>) {
+        let mut db = self.0.write().unwrap();
+        db.set_max_loaded_chests(max.map(|max| max as usize));
+        db.evict();
+    }
+
+    #[napi]
+    pub fn evict(&self) {
+        // Only take the write lock when chests will be unloaded, as this is called after
+        // every read
+        if self.0.read().unwrap().needs_eviction() {
+            self.0.write().unwrap().evict()
+        }
+    }
+
+    #[napi]
+    pub fn install(&self, path: String, parameters: Option<InstallParameters>) -> Result<()> {
+        let chest = docdelve::chest::Chest::open(std::path::Path::new(&path))?;
+        Ok(self.0.write().unwrap().install(
+            &chest,
+            parameters
+                .map(|parameters| parameters.into())
+                .unwrap_or_default(),
+            |_| (),
+        )?)
+    }
+
+    #[napi]
+    pub fn uninstall(&self, identifier: String) -> Result<()> {
+        Ok(self.0.write().unwrap().uninstall(&identifier)?)
+    }
+
+    #[napi]
+    pub fn reload(&self) -> Result<Vec<ChestChange>> {
+        Ok(docdelve::db::Database::reload_shared(&self.0)?
+            .iter()
+            .map(|change| change.into())
+            .collect())
+    }
+
+    #[napi(ts_args_type = "callback: (changes: ChestChange[]) => void")]
+    pub fn watch(&self, env: Env, callback: JsFunction) -> Result<DatabaseWatcher> {
+        let mut callback: ThreadsafeFunction<Vec<ChestChange>, ErrorStrategy::Fatal> =
+            callback.create_threadsafe_function(0, |ctx| Ok(vec![ctx.value]))?;
+
+        // Don't keep the event loop alive just to deliver changes
+        callback.unref(&env)?;
+        let watcher = docdelve::watch::DatabaseWatcher::new(self.0.clone(), move |changes| {
+            callback.call(
+                changes.iter().map(|change| change.into()).collect(),
+                ThreadsafeFunctionCallMode::NonBlocking,
+            );
+        })?;
+        Ok(DatabaseWatcher(Some(watcher)))
+    }
+
+    #[napi]
+    pub fn chest(&self, identifier: String) -> Result<Option<ChestContents>> {
+        let contents = self
+            .0
+            .read()
+            .unwrap()
+            .chest(&identifier)?
+            .map(|chest| chest.into());
+        self.evict();
+        Ok(contents)
+    }
+
+    #[napi]
+    pub fn chest_trust(&self, identifier: String) -> Option<ChestTrust> {
         self.0
             .read()
             .unwrap()
-            .chest(&identifier)
-            .map(|chest| chest.into())
+            .trust(&identifier)
+            .map(|trust| trust.into())
     }
 
     #[napi]
-    pub fn items_at_path(&self, path: ItemPath) -> Vec<ChestItem> {
-        let db = self.0.read().unwrap();
-        if let Some(chest) = db.chest(&path.identifier) {
-            db.items_at_path(&path.into())
-                .into_iter()
-                .map(|item| ChestItem::from(chest, item))
-                .collect()
-        } else {
-            Vec::new()
-        }
+    pub fn diagnostics(&self) -> Vec<ChestDiagnostic> {
+        self.0
+            .read()
+            .unwrap()
+            .diagnostics()
+            .iter()
+            .map(|diagnostic| diagnostic.into())
+            .collect()
     }
 
     #[napi]
-    pub fn item_contents_at_path(&self, path: ItemPath) -> ItemContents {
-        let db = self.0.read().unwrap();
-        if let Some(chest) = db.chest(&path.identifier) {
-            ItemContents::from(chest, db.item_contents_at_path(&path.into()))
-        } else {
-            ItemContents {
-                chest_items: Vec::new(),
-                page_items: Vec::new(),
-                bases: Vec::new(),
+    pub fn items_at_path(&self, path: ItemPath) -> Result<Vec<ChestItem>> {
+        let items = {
+            let db = self.0.read().unwrap();
+            if let Some(chest) = db.chest(&path.identifier)? {
+                db.items_at_path(&path.into())?
+                    .into_iter()
+                    .map(|item| ChestItem::from(chest, item))
+                    .collect()
+            } else {
+                Vec::new()
             }
-        }
+        };
+        self.evict();
+        Ok(items)
+    }
+
+    #[napi]
+    pub fn item_contents_at_path(&self, path: ItemPath) -> Result<ItemContents> {
+        let contents = {
+            let db = self.0.read().unwrap();
+            if let Some(chest) = db.chest(&path.identifier)? {
+                ItemContents::from(chest, db.item_contents_at_path(&path.into())?)
+            } else {
+                ItemContents {
+                    chest_items: Vec::new(),
+                    page_items: Vec::new(),
+                    bases: Vec::new(),
+                }
+            }
+        };
+        self.evict();
+        Ok(contents)
     }
 
     #[napi]
@@ -197,18 +350,21 @@ impl Database {
         path: Option<ItemPath>,
         query: String,
         parameters: Option<SearchParameters>,
-    ) -> Vec<SearchResult> {
-        self.0
+    ) -> Result<Vec<SearchResult>> {
+        let results = self
+            .0
             .read()
             .unwrap()
             .search(
                 path.map(|path| path.into()).as_ref(),
                 &query,
                 parameters.unwrap_or_default().into(),
-            )
+            )?
             .into_iter()
             .map(|result| result.into())
-            .collect()
+            .collect();
+        self.evict();
+        Ok(results)
     }
 
     #[napi]
@@ -227,13 +383,16 @@ impl Database {
         identifier: String,
         url: String,
         path: Option<ItemPath>,
-    ) -> Option<ItemPath> {
-        self.0
+    ) -> Result<Option<ItemPath>> {
+        let path = self
+            .0
             .read()
             .unwrap()
-            .item_for_path(&identifier, &url, path.map(|path| path.into()).as_ref())
+            .item_for_path(&identifier, &url, path.map(|path| path.into()).as_ref())?
             .as_ref()
-            .map(|path| path.into())
+            .map(|path| path.into());
+        self.evict();
+        Ok(path)
     }
 
     #[napi]
@@ -242,13 +401,16 @@ impl Database {
         identifier: String,
         url: String,
         path: Option<ItemPath>,
-    ) -> Option<ItemPath> {
-        self.0
+    ) -> Result<Option<ItemPath>> {
+        let path = self
+            .0
             .read()
             .unwrap()
-            .page_for_path(&identifier, &url, path.map(|path| path.into()).as_ref())
+            .page_for_path(&identifier, &url, path.map(|path| path.into()).as_ref())?
             .as_ref()
-            .map(|path| path.into())
+            .map(|path| path.into());
+        self.evict();
+        Ok(path)
     }
 
     #[napi]
@@ -510,6 +672,7 @@ impl From<docdelve::db::SearchParameters> for SearchParameters {
     fn from(parameters: docdelve::db::SearchParameters) -> Self {
         Self {
             result_count: parameters.result_count as u32,
+            scope: parameters.scope,
         }
     }
 }
@@ -518,6 +681,20 @@ impl From<SearchParameters> for docdelve::db::SearchParameters {
     fn from(parameters: SearchParameters) -> Self {
         Self {
             result_count: parameters.result_count as usize,
+            scope: parameters.scope,
+        }
+    }
+}
+
+impl From<InstallParameters> for docdelve::db::InstallParameters {
+    fn from(parameters: InstallParameters) -> Self {
+        Self {
+            verify: parameters.verify,
+            signature_policy: if parameters.require_signature {
+                docdelve::db::SignaturePolicy::Require
+            } else {
+                docdelve::db::SignaturePolicy::Flag
+            },
         }
     }
 }
@@ -564,6 +741,88 @@ impl From<&docdelve::content::FileReplacementRule> for FileReplacementRule {
     }
 }
 
+impl From<&docdelve::signature::ChestTrust> for ChestTrust {
+    fn from(trust: &docdelve::signature::ChestTrust) -> Self {
+        match trust {
+            docdelve::signature::ChestTrust::Trusted(_) => ChestTrust::Trusted,
+            docdelve::signature::ChestTrust::Untrusted => ChestTrust::Untrusted,
+            docdelve::signature::ChestTrust::Unsigned => ChestTrust::Unsigned,
+            docdelve::signature::ChestTrust::Invalid => ChestTrust::Invalid,
+        }
+    }
+}
+
+impl From<&docdelve::db::ChestDiagnostic> for ChestDiagnostic {
+    fn from(diagnostic: &docdelve::db::ChestDiagnostic) -> Self {
+        let path = diagnostic.path.to_string_lossy().to_string();
+        match &diagnostic.status {
+            docdelve::db::ChestLoadStatus::Loaded(identifier) => Self {
+                path,
+                status: ChestLoadStatus::Loaded,
+                identifier: Some(identifier.clone()),
+                error: None,
+            },
+            docdelve::db::ChestLoadStatus::Duplicate(identifier) => Self {
+                path,
+                status: ChestLoadStatus::Duplicate,
+                identifier: Some(identifier.clone()),
+                error: None,
+            },
+            docdelve::db::ChestLoadStatus::Failed(error) => Self {
+                path,
+                status: ChestLoadStatus::Failed,
+                identifier: None,
+                error: Some(format!("{:#}", error)),
+            },
+            docdelve::db::ChestLoadStatus::Ignored => Self {
+                path,
+                status: ChestLoadStatus::Ignored,
+                identifier: None,
+                error: None,
+            },
+            docdelve::db::ChestLoadStatus::InvalidKey(error) => Self {
+                path,
+                status: ChestLoadStatus::InvalidKey,
+                identifier: None,
+                error: Some(format!("{:#}", error)),
+            },
+            docdelve::db::ChestLoadStatus::TagCollision {
+                identifier,
+                name,
+                other_tags,
+            } => Self {
+                path,
+                status: ChestLoadStatus::TagCollision,
+                identifier: Some(identifier.clone()),
+                error: Some(format!(
+                    "Tag '{}' is also claimed by {}",
+                    name,
+                    other_tags.join(", ")
+                )),
+            },
+        }
+    }
+}
+
+impl From<&docdelve::db::ChestChange> for ChestChange {
+    fn from(change: &docdelve::db::ChestChange) -> Self {
+        match change {
+            docdelve::db::ChestChange::Added(identifier) => Self {
+                change_type: ChestChangeType::Added,
+                identifier: identifier.clone(),
+            },
+            docdelve::db::ChestChange::Removed(identifier) => Self {
+                change_type: ChestChangeType::Removed,
+                identifier: identifier.clone(),
+            },
+            docdelve::db::ChestChange::Updated(identifier) => Self {
+                change_type: ChestChangeType::Updated,
+                identifier: identifier.clone(),
+            },
+        }
+    }
+}
+
 impl From<Theme> for docdelve::db::Theme {
     fn from(theme: Theme) -> Self {
         match theme {
@@ -594,12 +853,40 @@ impl ItemContents {
     }
 }
 
+#[napi]
+impl DatabaseWatcher {
+    #[napi]
+    pub fn stop(&mut self) {
+        self.0.take();
+    }
+}
+
+impl Task for WarmUp {
+    type Output = ();
+    type JsValue = ();
+
+    fn compute(&mut self) -> napi::Result<Self::Output> {
+        docdelve::db::Database::warm_up_shared(&self.0);
+        Ok(())
+    }
+
+    fn resolve(&mut self, _env: Env, _output: Self::Output) -> napi::Result<Self::JsValue> {
+        Ok(())
+    }
+}
+
 impl From<anyhow::Error> for Error {
     fn from(err: anyhow::Error) -> Self {
         Error(napi::Error::new(Status::GenericFailure, &err.to_string()))
     }
 }
 
+impl From<napi::Error> for Error {
+    fn from(err: napi::Error) -> Self {
+        Error(err)
+    }
+}
+
 impl From<Error> for napi::Error {
     fn from(err: Error) -> Self {
         err.0
@@ -1,9 +1,13 @@
-use anyhow::Result;
+use anyhow::{anyhow, Result};
 use clap::{Args, Parser, Subcommand};
 use docdelve::chest::Chest;
 use docdelve::content::{ChestContents, ChestItem, IndexedChestItemData, ObjectType, PageItem};
-use docdelve::db::{Database, SearchParameters};
-use docdelve::progress::ProgressEvent;
+use docdelve::db::{
+    ChestLoadStatus, Database, InstallParameters, SearchParameters, SignaturePolicy,
+};
+use docdelve::delta::ChestDelta;
+use docdelve::progress::default_terminal_progress_event_handler;
+use docdelve::signature::ChestSigningKey;
 use std::path::PathBuf;
 
 #[derive(Parser)]
@@ -17,7 +21,16 @@ enum Commands {
     Extract(ExtractArgs),
     List(ListArgs),
     Install(InstallArgs),
+    Uninstall(UninstallArgs),
     Search(SearchArgs),
+    Verify(VerifyArgs),
+    Keygen(KeygenArgs),
+    Sign(SignArgs),
+    Trust(TrustArgs),
+    Delta(DeltaArgs),
+    ApplyDelta(ApplyDeltaArgs),
+    Pack(PackArgs),
+    Doctor,
 }
 
 #[derive(Args)]
@@ -34,11 +47,72 @@ struct ListArgs {
 #[derive(Args)]
 struct InstallArgs {
     chest: PathBuf,
+    #[clap(long)]
+    verify: bool,
+    #[clap(long)]
+    require_signature: bool,
+}
+
+#[derive(Args)]
+struct UninstallArgs {
+    /// Tag (optionally with a version or version selector, such as `Qt@6.5.0` or `Qt@6`) or
+    /// identifier of the chest
+    chest: String,
 }
 
 #[derive(Args)]
 struct SearchArgs {
     query: String,
+    /// Only search the chest with this tag, optionally with a version or version selector
+    /// such as `Qt@6`, `Qt@~6.5`, `Qt@>=6.2,<6.6` or `Rust@stable`
+    #[clap(long)]
+    scope: Option<String>,
+}
+
+#[derive(Args)]
+struct VerifyArgs {
+    chest: PathBuf,
+}
+
+#[derive(Args)]
+struct KeygenArgs {
+    name: String,
+}
+
+#[derive(Args)]
+struct SignArgs {
+    chest: PathBuf,
+    #[clap(short, long)]
+    key: PathBuf,
+    #[clap(short, long)]
+    output: Option<PathBuf>,
+}
+
+#[derive(Args)]
+struct TrustArgs {
+    public_key: PathBuf,
+}
+
+#[derive(Args)]
+struct DeltaArgs {
+    source: PathBuf,
+    target: PathBuf,
+    output: PathBuf,
+}
+
+#[derive(Args)]
+struct ApplyDeltaArgs {
+    source: PathBuf,
+    delta: PathBuf,
+    output: PathBuf,
+}
+
+#[derive(Args)]
+struct PackArgs {
+    dir: PathBuf,
+    output: PathBuf,
+    #[clap(short, long)]
+    contents: PathBuf,
 }
 
 pub fn main() -> Result<()> {
@@ -47,12 +121,10 @@ pub fn main() -> Result<()> {
     match &cli.command {
         Commands::Extract(extract) => {
             let chest = Chest::open(&extract.chest)?;
-            chest.extract(&extract.target, |event| match event {
-                ProgressEvent::ExtractChest(done, total) => {
-                    print!("\r\x1b[2KExtracting chest ({}%)...", (done * 100) / total)
-                }
-                _ => (),
-            })?;
+            chest.extract(
+                &extract.target,
+                default_terminal_progress_event_handler(false),
+            )?;
             println!("\r\x1b[2KExtract completed");
         }
         Commands::List(list) => {
@@ -63,13 +135,54 @@ pub fn main() -> Result<()> {
         Commands::Install(install) => {
             let chest = Chest::open(&install.chest)?;
             let mut db = Database::load()?;
-            db.install(&chest)?;
+            print_load_errors(&db);
+            db.install(
+                &chest,
+                InstallParameters {
+                    verify: install.verify,
+                    signature_policy: if install.require_signature {
+                        SignaturePolicy::Require
+                    } else {
+                        SignaturePolicy::Flag
+                    },
+                },
+                default_terminal_progress_event_handler(false),
+            )?;
+            if install.verify || install.require_signature {
+                println!("\r\x1b[2KInstall completed");
+            }
+        }
+        Commands::Uninstall(uninstall) => {
+            let mut db = Database::load()?;
+            print_load_errors(&db);
+            let identifier = db
+                .identifier_for_tag(&uninstall.chest)
+                .unwrap_or_else(|| uninstall.chest.clone());
+            let tag = db
+                .tag_for_identifier(&identifier)
+                .ok_or_else(|| anyhow!("Chest '{}' is not installed", uninstall.chest))?;
+            db.uninstall(&identifier)?;
+            println!("Uninstalled {}", tag);
         }
         Commands::Search(search) => {
             let db = Database::load()?;
+            print_load_errors(&db);
+
+            if let Some(scope) = &search.scope {
+                if db.identifier_for_tag(scope).is_none() {
+                    return Err(anyhow!("Chest '{}' is not installed", scope));
+                }
+            }
 
             let start = std::time::Instant::now();
-            let results = db.search(None, &search.query, SearchParameters::default());
+            let results = db.search(
+                None,
+                &search.query,
+                SearchParameters {
+                    scope: search.scope.clone(),
+                    ..SearchParameters::default()
+                },
+            )?;
             let t = std::time::Instant::now().duration_since(start);
             println!("Search completed in {}ms", t.as_millis());
 
@@ -82,7 +195,7 @@ pub fn main() -> Result<()> {
                     result.path.chest_path,
                     result.score
                 );
-                for item in db.items_at_path(&result.path) {
+                for item in db.items_at_path(&result.path)? {
                     if let IndexedChestItemData::Object(obj) = &item.data {
                         if let Some(decl) = &obj.info.declaration {
                             println!("  {}", decl);
@@ -91,11 +204,151 @@ pub fn main() -> Result<()> {
                 }
             }
         }
+        Commands::Verify(verify) => {
+            let chest = Chest::open(&verify.chest)?;
+            chest.verify(default_terminal_progress_event_handler(false))?;
+            println!("\r\x1b[2KChest contents verified");
+        }
+        Commands::Keygen(keygen) => {
+            let key = ChestSigningKey::generate()?;
+            key.write_to_file(&PathBuf::from(format!("{}.key", keygen.name)))?;
+            std::fs::write(format!("{}.pub", keygen.name), key.public_key())?;
+            println!("Public key: {}", key.public_key());
+        }
+        Commands::Sign(sign) => {
+            let mut chest = Chest::open(&sign.chest)?;
+            chest.set_signing_key(Some(ChestSigningKey::from_file(&sign.key)?));
+            if let Some(output) = &sign.output {
+                chest.save(output, default_terminal_progress_event_handler(false))?;
+            } else {
+                // Sign in place by saving to a temporary file and replacing the original
+                let mut temp_path = sign.chest.clone().into_os_string();
+                temp_path.push(".tmp");
+                let temp_path = PathBuf::from(temp_path);
+                chest.save(&temp_path, default_terminal_progress_event_handler(false))?;
+                drop(chest);
+                std::fs::rename(&temp_path, &sign.chest)?;
+            }
+            println!("\r\x1b[2KChest signed");
+        }
+        Commands::Trust(trust) => {
+            let name = trust
+                .public_key
+                .file_stem()
+                .ok_or_else(|| anyhow!("Public key path has no filename"))?
+                .to_string_lossy()
+                .to_string();
+            let public_key = std::fs::read_to_string(&trust.public_key)?;
+            let mut db = Database::load()?;
+            db.trust_key(&name, &public_key)?;
+            println!("Trusted key '{}'", name);
+        }
+        Commands::Delta(delta) => {
+            let source = Chest::open(&delta.source)?;
+            let target = Chest::open(&delta.target)?;
+            let mut result = ChestDelta::create(&source, &target)?;
+            result.save(
+                &delta.output,
+                default_terminal_progress_event_handler(false),
+            )?;
+            println!(
+                "\r\x1b[2KDelta from version {} to {}: {} modified, {} removed",
+                result.info().source_version,
+                result.info().target_version,
+                result.info().modified.len(),
+                result.info().removed.len()
+            );
+        }
+        Commands::ApplyDelta(apply) => {
+            let source = Chest::open(&apply.source)?;
+            let delta = ChestDelta::open(&apply.delta)?;
+            delta.apply(
+                source,
+                &apply.output,
+                default_terminal_progress_event_handler(false),
+            )?;
+            println!("\r\x1b[2KDelta applied");
+        }
+        Commands::Pack(pack) => {
+            let contents: ChestContents = serde_json::from_slice(&std::fs::read(&pack.contents)?)?;
+            let mut chest = Chest::from_dir(&pack.dir)?;
+            contents.write_to_chest(&mut chest)?;
+            chest.save(&pack.output, default_terminal_progress_event_handler(false))?;
+            println!("\r\x1b[2KPack completed");
+        }
+        Commands::Doctor => {
+            let db = Database::load()?;
+            let mut problems = 0;
+            let mut collisions = 0;
+            for diagnostic in db.diagnostics() {
+                let path = diagnostic.path.display();
+                match &diagnostic.status {
+                    ChestLoadStatus::Loaded(identifier) => {
+                        let tag = db.tag_for_identifier(identifier).unwrap_or_default();
+                        println!("OK         {} ({})", path, tag);
+                    }
+                    ChestLoadStatus::Duplicate(identifier) => {
+                        println!(
+                            "DUPLICATE  {} (identifier {} already loaded)",
+                            path, identifier
+                        );
+                        problems += 1;
+                    }
+                    ChestLoadStatus::Failed(error) => {
+                        println!("FAILED     {}: {:#}", path, error);
+                        problems += 1;
+                    }
+                    ChestLoadStatus::Ignored => println!("IGNORED    {} (not a chest)", path),
+                    ChestLoadStatus::InvalidKey(error) => {
+                        println!("BAD KEY    {}: {:#}", path, error);
+                        problems += 1;
+                    }
+                    ChestLoadStatus::TagCollision {
+                        identifier,
+                        name,
+                        other_tags,
+                    } => {
+                        println!(
+                            "COLLISION  {} ({} claims tag '{}', also claimed by {})",
+                            path,
+                            identifier,
+                            name,
+                            other_tags.join(", ")
+                        );
+                        collisions += 1;
+                    }
+                }
+            }
+            if problems != 0 {
+                return Err(anyhow!(
+                    "{} chest(s) or trusted key(s) could not be loaded",
+                    problems
+                ));
+            } else if collisions != 0 {
+                return Err(anyhow!("{} tag collision(s) found", collisions));
+            } else {
+                println!("All chests loaded");
+            }
+        }
     }
 
     Ok(())
 }
 
+fn print_load_errors(db: &Database) {
+    for diagnostic in db.diagnostics() {
+        match &diagnostic.status {
+            ChestLoadStatus::Failed(error) => eprintln!(
+                "Warning: chest '{}' could not be loaded: {:#}",
+                diagnostic.path.display(),
+                error
+            ),
+            ChestLoadStatus::InvalidKey(error) => eprintln!("Warning: {:#}", error),
+            _ => (),
+        }
+    }
+}
+
 fn dump_contents(contents: &ChestContents) {
     println!(
         "{} version {}, start page '{}'",